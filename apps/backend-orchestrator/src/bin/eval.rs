@@ -0,0 +1,532 @@
+//! Retrieval evaluation against a golden question set.
+//!
+//! Runs each question through the retrieval and prompt injection screening
+//! of `/api/chat` and reports
+//! recall@k, MRR and nDCG@k, for one or two retrieval configurations.
+//!
+//! ```text
+//! eval run <questions.jsonl|questions.yaml> [--config <options.json> [--config <options.json>]] [--output <report.json>]
+//! eval diff <baseline.json> <candidate.json> [--max-drop <value>]
+//! ```
+//!
+//! A question file has one JSON object per line:
+//! `{"question": "...", "expected_document_ids": ["..."], "expected_chunk_ids": ["..."]}`,
+//! optionally with `language`, or, with a `.yaml`/`.yml` extension, a YAML
+//! list of such objects. A config file holds the retrieval options of a
+//! `/api/chat` request (`retrieval_mode`, `top_k`, `semantic_ratio`, `filter`, ...).
+//!
+//! With two configs, `run` prints the difference between them; `--output`
+//! saves the report of a single config for a later `diff`. `diff` exits with
+//! status 1 if a metric of the candidate is more than `--max-drop` (default 0)
+//! below the baseline, to gate changes in CI.
+
+use rag_backend_orchestrator::error::{RagError, Result};
+use rag_backend_orchestrator::models::{Language, RetrievalOptions, SearchResult};
+use rag_backend_orchestrator::rag::{InjectionScreener, RetrievalService, language};
+use rag_backend_orchestrator::services::{
+    EmbeddingService, LlmService, MeilisearchService, RerankerService,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::{env, fs};
+
+const USAGE: &str = "Usage:
+  eval run <questions.jsonl|questions.yaml> [--config <options.json> [--config <options.json>]] [--output <report.json>]
+  eval diff <baseline.json> <candidate.json> [--max-drop <value>]";
+
+#[derive(Debug, Deserialize)]
+struct GoldenQuestion {
+    question: String,
+    #[serde(default)]
+    expected_document_ids: Vec<String>,
+    #[serde(default)]
+    expected_chunk_ids: Vec<String>,
+    language: Option<Language>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+struct Metrics {
+    recall: f64,
+    mrr: f64,
+    ndcg: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct QuestionReport {
+    question: String,
+    retrieved_ids: Vec<String>,
+    #[serde(flatten)]
+    metrics: Metrics,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Report {
+    /// Config file the report was produced with, `default` without one
+    config: String,
+    k: usize,
+    #[serde(flatten)]
+    metrics: Metrics,
+    questions: Vec<QuestionReport>,
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("warn")),
+        )
+        .init();
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = match args.first().map(String::as_str) {
+        Some("run") => run(&args[1..]).await,
+        Some("diff") => diff(&args[1..]),
+        _ => Err(RagError::InvalidRequest(USAGE.to_owned())),
+    };
+
+    match result {
+        Ok(passed) if passed => ExitCode::SUCCESS,
+        Ok(_) => ExitCode::FAILURE,
+        Err(err) => {
+            eprintln!("{}", err);
+            ExitCode::from(2)
+        }
+    }
+}
+
+async fn run(args: &[String]) -> Result<bool> {
+    let mut questions_path = None;
+    let mut configs = Vec::new();
+    let mut output = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" => configs.push(flag_value(&mut args, "--config")?),
+            "--output" => output = Some(flag_value(&mut args, "--output")?),
+            path if questions_path.is_none() => questions_path = Some(path.to_owned()),
+            other => {
+                return Err(RagError::InvalidRequest(format!(
+                    "Unexpected argument '{}'\n{}",
+                    other, USAGE
+                )));
+            }
+        }
+    }
+    let questions_path =
+        questions_path.ok_or_else(|| RagError::InvalidRequest(USAGE.to_owned()))?;
+    if configs.len() > 2 || (configs.len() == 2 && output.is_some()) {
+        return Err(RagError::InvalidRequest(
+            "At most two configs can be compared, --output takes a single config".to_owned(),
+        ));
+    }
+
+    let questions = load_questions(&questions_path)?;
+    let retrieval_service = retrieval_service().await?;
+
+    let mut reports = Vec::new();
+    for config in configs_or_default(&configs) {
+        reports.push(evaluate(&retrieval_service, &questions, config).await?);
+    }
+
+    for report in &reports {
+        print_report(report);
+    }
+    if let [baseline, candidate] = reports.as_slice() {
+        print_diff(baseline, candidate);
+    }
+
+    if let (Some(output), [report]) = (output, reports.as_slice()) {
+        fs::write(&output, serde_json::to_string_pretty(report)?)?;
+        println!("Report written to {}", output);
+    }
+
+    Ok(true)
+}
+
+fn diff(args: &[String]) -> Result<bool> {
+    let mut paths = Vec::new();
+    let mut max_drop = 0.0;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--max-drop" => {
+                max_drop = flag_value(&mut args, "--max-drop")?.parse().map_err(|_| {
+                    RagError::InvalidRequest("--max-drop must be a number".to_owned())
+                })?;
+            }
+            path => paths.push(path.to_owned()),
+        }
+    }
+    let [baseline, candidate] = paths.as_slice() else {
+        return Err(RagError::InvalidRequest(USAGE.to_owned()));
+    };
+
+    let baseline: Report = serde_json::from_str(&fs::read_to_string(baseline)?)?;
+    let candidate: Report = serde_json::from_str(&fs::read_to_string(candidate)?)?;
+    print_diff(&baseline, &candidate);
+
+    let regressions: Vec<&str> = [
+        ("recall", baseline.metrics.recall, candidate.metrics.recall),
+        ("mrr", baseline.metrics.mrr, candidate.metrics.mrr),
+        ("ndcg", baseline.metrics.ndcg, candidate.metrics.ndcg),
+    ]
+    .into_iter()
+    .filter(|(_, before, after)| before - after > max_drop)
+    .map(|(name, _, _)| name)
+    .collect();
+
+    if regressions.is_empty() {
+        Ok(true)
+    } else {
+        println!(
+            "Regression beyond {} in: {}",
+            max_drop,
+            regressions.join(", ")
+        );
+        Ok(false)
+    }
+}
+
+fn flag_value<'a>(args: &mut impl Iterator<Item = &'a String>, flag: &str) -> Result<String> {
+    args.next()
+        .cloned()
+        .ok_or_else(|| RagError::InvalidRequest(format!("{} needs a value", flag)))
+}
+
+fn configs_or_default(configs: &[String]) -> Vec<Option<&str>> {
+    if configs.is_empty() {
+        vec![None]
+    } else {
+        configs.iter().map(|config| Some(config.as_str())).collect()
+    }
+}
+
+fn load_questions(path: &str) -> Result<Vec<GoldenQuestion>> {
+    let content = fs::read_to_string(path)?;
+
+    // (position for error messages, question)
+    let questions: Vec<(String, GoldenQuestion)> =
+        if path.ends_with(".yaml") || path.ends_with(".yml") {
+            let questions: Vec<GoldenQuestion> = serde_yaml::from_str(&content)
+                .map_err(|err| RagError::InvalidRequest(format!("{}: {}", path, err)))?;
+            questions
+                .into_iter()
+                .enumerate()
+                .map(|(number, question)| (format!("question {}", number + 1), question))
+                .collect()
+        } else {
+            let mut questions = Vec::new();
+            for (number, line) in content.lines().enumerate() {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let question: GoldenQuestion = serde_json::from_str(line).map_err(|err| {
+                    RagError::InvalidRequest(format!("{} line {}: {}", path, number + 1, err))
+                })?;
+                questions.push((format!("line {}", number + 1), question));
+            }
+            questions
+        };
+
+    questions
+        .into_iter()
+        .map(|(position, question)| {
+            if question.expected_document_ids.is_empty() && question.expected_chunk_ids.is_empty() {
+                return Err(RagError::InvalidRequest(format!(
+                    "{} {}: no expected document or chunk ids",
+                    path, position
+                )));
+            }
+            Ok(question)
+        })
+        .collect()
+}
+
+/// Builds the retrieval service as the server does, from the same environment.
+async fn retrieval_service() -> Result<RetrievalService> {
+    let meilisearch_service = MeilisearchService::new().await?;
+
+    Ok(RetrievalService::new(
+        Arc::new(EmbeddingService::new()),
+        Arc::new(meilisearch_service),
+        Arc::new(LlmService::new()?),
+        Arc::new(RerankerService::new()),
+        Arc::new(InjectionScreener::new()?),
+    ))
+}
+
+async fn evaluate(
+    retrieval_service: &RetrievalService,
+    questions: &[GoldenQuestion],
+    config: Option<&str>,
+) -> Result<Report> {
+    let options: RetrievalOptions = match config {
+        Some(path) => serde_json::from_str(&fs::read_to_string(path)?)?,
+        None => RetrievalOptions::default(),
+    };
+    let mut params = retrieval_service.resolve_params(&options)?;
+    let k = params.top_k;
+
+    let mut question_reports = Vec::new();
+    for question in questions {
+        // Boost the question language like the chat pipeline does
+        params.boost_language = Some(
+            question
+                .language
+                .unwrap_or_else(|| language::detect(&question.question)),
+        );
+        let results = retrieval_service
+            .retrieve(&question.question, &params)
+            .await?;
+
+        question_reports.push(QuestionReport {
+            question: question.question.clone(),
+            retrieved_ids: results.iter().map(|result| result.id.clone()).collect(),
+            metrics: score(question, &results, k),
+        });
+    }
+
+    let count = question_reports.len().max(1) as f64;
+    let mean = |metric: fn(&Metrics) -> f64| {
+        question_reports
+            .iter()
+            .map(|report| metric(&report.metrics))
+            .sum::<f64>()
+            / count
+    };
+
+    Ok(Report {
+        config: config.unwrap_or("default").to_owned(),
+        k,
+        metrics: Metrics {
+            recall: mean(|metrics| metrics.recall),
+            mrr: mean(|metrics| metrics.mrr),
+            ndcg: mean(|metrics| metrics.ndcg),
+        },
+        questions: question_reports,
+    })
+}
+
+/// Scores the top `k` results against the expected ids. A result is relevant
+/// if it is an expected chunk or belongs to an expected document; each expected
+/// id counts once, so several chunks of one expected document don't add up.
+fn score(question: &GoldenQuestion, results: &[SearchResult], k: usize) -> Metrics {
+    let expected: HashSet<&str> = question
+        .expected_chunk_ids
+        .iter()
+        .chain(&question.expected_document_ids)
+        .map(String::as_str)
+        .collect();
+
+    let mut found: HashSet<&str> = HashSet::new();
+    let mut first_relevant_rank = None;
+    let mut dcg = 0.0;
+
+    for (rank, result) in results.iter().take(k).enumerate() {
+        let matched = [Some(result.id.as_str()), result.document_id.as_deref()]
+            .into_iter()
+            .flatten()
+            .find(|id| expected.contains(id) && !found.contains(id));
+
+        if let Some(id) = matched {
+            found.insert(id);
+            first_relevant_rank.get_or_insert(rank + 1);
+            dcg += 1.0 / (rank as f64 + 2.0).log2();
+        }
+    }
+
+    let ideal_dcg: f64 = (0..expected.len().min(k))
+        .map(|rank| 1.0 / (rank as f64 + 2.0).log2())
+        .sum();
+
+    Metrics {
+        recall: found.len() as f64 / expected.len() as f64,
+        mrr: first_relevant_rank.map_or(0.0, |rank| 1.0 / rank as f64),
+        ndcg: if ideal_dcg > 0.0 {
+            dcg / ideal_dcg
+        } else {
+            0.0
+        },
+    }
+}
+
+fn print_report(report: &Report) {
+    println!(
+        "{} ({} questions, k = {}): recall@{} {:.3}  MRR {:.3}  nDCG@{} {:.3}",
+        report.config,
+        report.questions.len(),
+        report.k,
+        report.k,
+        report.metrics.recall,
+        report.metrics.mrr,
+        report.k,
+        report.metrics.ndcg
+    );
+}
+
+fn print_diff(baseline: &Report, candidate: &Report) {
+    println!("{} -> {}", baseline.config, candidate.config);
+    for (name, before, after) in [
+        ("recall", baseline.metrics.recall, candidate.metrics.recall),
+        ("MRR", baseline.metrics.mrr, candidate.metrics.mrr),
+        ("nDCG", baseline.metrics.ndcg, candidate.metrics.ndcg),
+    ] {
+        println!(
+            "  {:<6} {:.3} -> {:.3} ({:+.3})",
+            name,
+            before,
+            after,
+            after - before
+        );
+    }
+
+    // Questions whose reciprocal rank changed, to see what the change affects
+    for (before, after) in baseline.questions.iter().zip(&candidate.questions) {
+        if before.question == after.question
+            && (before.metrics.mrr - after.metrics.mrr).abs() > f64::EPSILON
+        {
+            println!(
+                "  {:+.3} MRR  {}",
+                after.metrics.mrr - before.metrics.mrr,
+                before.question
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rag_backend_orchestrator::models::DocumentMetadata;
+
+    fn question(documents: &[&str], chunks: &[&str]) -> GoldenQuestion {
+        GoldenQuestion {
+            question: "question".to_owned(),
+            expected_document_ids: documents.iter().map(|id| id.to_string()).collect(),
+            expected_chunk_ids: chunks.iter().map(|id| id.to_string()).collect(),
+            language: None,
+        }
+    }
+
+    fn result(id: &str, document_id: &str) -> SearchResult {
+        SearchResult {
+            id: id.to_owned(),
+            title: String::new(),
+            content: String::new(),
+            metadata: DocumentMetadata::default(),
+            source_file: None,
+            document_id: Some(document_id.to_owned()),
+            chunk_index: None,
+            rerank_score: None,
+            ranking_score: None,
+            merged_chunk_ids: Vec::new(),
+        }
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn perfect_ranking_scores_one() {
+        let metrics = score(
+            &question(&["a", "b"], &[]),
+            &[result("a-0", "a"), result("b-0", "b")],
+            5,
+        );
+
+        assert_close(metrics.recall, 1.0);
+        assert_close(metrics.mrr, 1.0);
+        assert_close(metrics.ndcg, 1.0);
+    }
+
+    #[test]
+    fn first_relevant_hit_at_rank_three() {
+        let metrics = score(
+            &question(&["a"], &[]),
+            &[result("x-0", "x"), result("y-0", "y"), result("a-0", "a")],
+            5,
+        );
+
+        assert_close(metrics.recall, 1.0);
+        assert_close(metrics.mrr, 1.0 / 3.0);
+        // DCG 1/log2(4), ideal DCG 1/log2(2)
+        assert_close(metrics.ndcg, 0.5);
+    }
+
+    #[test]
+    fn recall_only_counts_the_top_k() {
+        let metrics = score(
+            &question(&["a", "b"], &[]),
+            &[result("a-0", "a"), result("x-0", "x"), result("b-0", "b")],
+            2,
+        );
+
+        assert_close(metrics.recall, 0.5);
+        assert_close(metrics.mrr, 1.0);
+        // Ideal DCG over min(expected, k) = 2 ranks
+        assert_close(metrics.ndcg, 1.0 / (1.0 + 1.0 / 3f64.log2()));
+    }
+
+    #[test]
+    fn chunks_of_one_expected_document_count_once() {
+        let metrics = score(
+            &question(&["a", "b"], &[]),
+            &[result("a-0", "a"), result("a-1", "a")],
+            5,
+        );
+
+        assert_close(metrics.recall, 0.5);
+        assert_close(metrics.ndcg, 1.0 / (1.0 + 1.0 / 3f64.log2()));
+    }
+
+    #[test]
+    fn expected_chunk_ids_match_by_chunk() {
+        let metrics = score(
+            &question(&[], &["a-1"]),
+            &[result("a-0", "a"), result("a-1", "a")],
+            5,
+        );
+
+        assert_close(metrics.recall, 1.0);
+        assert_close(metrics.mrr, 0.5);
+    }
+
+    #[test]
+    fn no_relevant_hit_scores_zero() {
+        let metrics = score(&question(&["a"], &[]), &[result("x-0", "x")], 5);
+
+        assert_close(metrics.recall, 0.0);
+        assert_close(metrics.mrr, 0.0);
+        assert_close(metrics.ndcg, 0.0);
+    }
+
+    #[test]
+    fn loads_yaml_questions() {
+        let path = env::temp_dir().join(format!("eval-questions-{}.yaml", std::process::id()));
+        fs::write(
+            &path,
+            "- question: Was ist RAG?\n  expected_document_ids: [rag]\n  language: de\n\
+             - question: What is hybrid search?\n  expected_chunk_ids: [search-2]\n",
+        )
+        .unwrap();
+
+        let questions = load_questions(path.to_str().unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(questions.len(), 2);
+        assert_eq!(questions[0].expected_document_ids, ["rag"]);
+        assert_eq!(questions[0].language, Some(Language::De));
+        assert_eq!(questions[1].expected_chunk_ids, ["search-2"]);
+    }
+}
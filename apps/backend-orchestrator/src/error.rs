@@ -1,5 +1,7 @@
 use thiserror::Error;
 
+pub type Result<T> = std::result::Result<T, RagError>;
+
 #[derive(Error, Debug)]
 pub enum RagError {
     #[error("Meilisearch error: {0}")]
@@ -8,6 +10,21 @@ pub enum RagError {
     #[error("OpenAI API error: {0}")]
     OpenAi(#[from] async_openai::error::OpenAIError),
 
+    #[error("LLM request failed: {0}")]
+    LlmFailed(String),
+
+    #[error("Embedding request failed: {0}")]
+    EmbeddingFailed(String),
+
+    #[error("Invalid request: {0}")]
+    InvalidRequest(String),
+
+    #[error("Not found: {0}")]
+    NotFound(String),
+
+    #[error("Configuration error: {0}")]
+    Config(String),
+
     #[error("HTTP client error: {0}")]
     Http(#[from] reqwest::Error),
 
@@ -0,0 +1,14 @@
+//! RAG pipeline of the backend orchestrator, shared by the HTTP server and the
+//! retrieval evaluation (`src/bin/eval.rs`).
+
+// Services are configured from the environment in `new()`, a `Default` would hide that
+#![allow(clippy::new_without_default)]
+
+pub mod error;
+pub mod ingestion;
+pub mod models;
+pub mod rag;
+pub mod services;
+
+#[cfg(test)]
+mod test_support;
@@ -1,30 +1,49 @@
 use axum::{
     Json, Router,
-    extract::{Multipart, State},
-    http::StatusCode,
+    extract::{Multipart, Path, State},
+    http::{HeaderName, StatusCode, header},
     response::{Sse, sse::Event},
-    routing::{get, post},
+    routing::{delete, get, post},
 };
+use futures::StreamExt;
 use std::{convert::Infallible, sync::Arc, time::Duration};
 use tower_http::cors::CorsLayer;
 use tracing::info;
 
-mod error;
-mod ingestion;
-mod models;
-mod rag;
-mod services;
+mod openai;
 
-use models::{DocumentListResponse, UploadResponse};
-use services::MeilisearchService;
+use rag_backend_orchestrator::{error, models, rag, services};
 
-use crate::models::{ChatRequest, InputDocument};
+use models::{
+    DocumentListResponse, Feedback, FeedbackRequest, FeedbackStats, PromptTemplateListResponse,
+    SearchExplainRequest, SearchExplanation, Session, SessionListResponse, SummaryStreamEvent,
+    UploadResponse,
+};
+use rag::{
+    ChatAgent, ChatPipeline, ChatTurn, ContextBuilder, DocumentSummarizer, GenerationService,
+    InjectionScreener, PromptPreview, PromptTemplates, RetrievalService, language,
+};
+use services::{
+    AnswerCache, EmbeddingService, FeedbackStore, GenerationRegistry, LlmService,
+    MeilisearchService, RerankerService, SessionStore, generations::GenerationMetrics,
+};
+
+use crate::models::{ChatRequest, ChatStreamEvent, InputDocument};
 
 // Application state
 #[derive(Clone)]
 struct AppState {
     meilisearch_service: Arc<MeilisearchService>,
-    // generation_service: Arc<GenerationService>,
+    retrieval_service: Arc<RetrievalService>,
+    generation_service: Arc<GenerationService>,
+    prompt_templates: Arc<PromptTemplates>,
+    session_store: Arc<SessionStore>,
+    chat_pipeline: Arc<ChatPipeline>,
+    generations: Arc<GenerationRegistry>,
+    answer_cache: Arc<AnswerCache>,
+    feedback_store: Arc<FeedbackStore>,
+    document_summarizer: Arc<DocumentSummarizer>,
+    injection_screener: Arc<InjectionScreener>,
 }
 
 #[tokio::main]
@@ -38,19 +57,86 @@ async fn main() -> Result<(), String> {
             .await
             .map_err(|err| err.to_string())?,
     );
-    // let generation_service = Arc::new(GenerationService::new()?);
+    let llm_service = Arc::new(LlmService::new().map_err(|err| err.to_string())?);
+    let embedding_service = Arc::new(EmbeddingService::new());
+    let injection_screener = Arc::new(InjectionScreener::new().map_err(|err| err.to_string())?);
+    let retrieval_service = Arc::new(RetrievalService::new(
+        embedding_service,
+        meilisearch_service.clone(),
+        llm_service.clone(),
+        Arc::new(RerankerService::new()),
+        injection_screener.clone(),
+    ));
+    let document_summarizer = Arc::new(DocumentSummarizer::new(
+        meilisearch_service.clone(),
+        llm_service.clone(),
+        injection_screener.clone(),
+    ));
+    let prompt_templates = Arc::new(PromptTemplates::new());
+    let generation_service = Arc::new(GenerationService::new(
+        llm_service,
+        prompt_templates.clone(),
+    ));
+    let session_store = Arc::new(SessionStore::new());
+    let answer_cache = Arc::new(AnswerCache::new());
+    let feedback_store = Arc::new(FeedbackStore::new().map_err(|err| err.to_string())?);
+    let chat_pipeline = Arc::new(ChatPipeline::new(
+        retrieval_service.clone(),
+        generation_service.clone(),
+        Arc::new(ContextBuilder::new()),
+        session_store.clone(),
+        Arc::new(ChatAgent::new(
+            retrieval_service.clone(),
+            generation_service.clone(),
+            session_store.clone(),
+        )),
+        answer_cache.clone(),
+    ));
 
     let app_state = AppState {
         meilisearch_service,
-        // generation_service,
+        retrieval_service,
+        generation_service,
+        prompt_templates,
+        session_store,
+        chat_pipeline,
+        generations: Arc::new(GenerationRegistry::new()),
+        answer_cache,
+        feedback_store,
+        document_summarizer,
+        injection_screener,
     };
 
     // Create router with routes
     let app = Router::new()
         .route("/health", get(health_check))
         .route("/api/chat", post(chat_handler))
+        .route("/api/chat/{request_id}/cancel", post(cancel_chat_handler))
+        .route("/api/metrics/generations", get(generation_metrics_handler))
+        .route("/api/feedback", post(submit_feedback_handler))
+        .route("/api/feedback/export", get(export_feedback_handler))
+        .route("/api/feedback/stats", get(feedback_stats_handler))
+        .route("/v1/models", get(openai::list_models_handler))
+        .route(
+            "/v1/chat/completions",
+            post(openai::chat_completions_handler),
+        )
+        .route("/api/search/explain", post(explain_search_handler))
         .route("/api/documents", post(upload_document_handler))
         .route("/api/documents", get(list_documents_handler))
+        .route("/api/documents/{id}", delete(delete_document_handler))
+        .route(
+            "/api/documents/{id}/summarize",
+            post(summarize_document_handler),
+        )
+        .route("/api/prompts", get(list_prompts_handler))
+        .route("/api/prompts/reload", post(reload_prompts_handler))
+        .route("/api/prompts/preview", post(preview_prompt_handler))
+        .route("/api/sessions", get(list_sessions_handler))
+        .route(
+            "/api/sessions/{id}",
+            get(get_session_handler).delete(delete_session_handler),
+        )
         .with_state(app_state)
         .layer(CorsLayer::permissive());
 
@@ -75,37 +161,153 @@ async fn health_check() -> &'static str {
 async fn chat_handler(
     State(state): State<AppState>,
     Json(payload): Json<ChatRequest>,
-) -> Sse<impl futures::Stream<Item = Result<Event, Infallible>>> {
+) -> Result<
+    (
+        [(HeaderName, String); 1],
+        Sse<impl futures::Stream<Item = Result<Event, Infallible>>>,
+    ),
+    (StatusCode, String),
+> {
     info!("Chat Request empfangen: {}", payload.message);
 
-    let user_message = payload.message.clone();
+    let retrieval = state
+        .retrieval_service
+        .resolve_params(&payload.retrieval)
+        .map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))?;
+    state
+        .prompt_templates
+        .validate(
+            payload.prompt_template.as_deref(),
+            payload
+                .language
+                .unwrap_or_else(|| language::detect(&payload.message)),
+        )
+        .map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))?;
+    let generation = state
+        .generation_service
+        .resolve_params(&payload.generation)
+        .map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))?;
 
-    let stream = async_stream::stream! {
-        let res = state.meilisearch_service.hybrid_search(&user_message, 5, None).await;
-
-        match res {
-            Ok(results) => {
-                for result in results {
-                    let json = serde_json::to_string(&result).unwrap_or_default();
-                    let event = Event::default().data(json);
-                    yield Ok(event);
-                }
-            },
-            Err(err) => {
-                let error_event = Event::default().data(format!("Search error: {}", err));
-                yield Ok(error_event);
-            },
-        }
+    // Generated here, client-chosen ids could be guessed by others to cancel the generation
+    let request_id = uuid::Uuid::now_v7().to_string();
+    let guard = state.generations.register(request_id.clone());
+
+    let events = state.chat_pipeline.clone().run(
+        ChatTurn {
+            message: payload.message,
+            session_id: payload.session_id,
+            history: Vec::new(),
+            retrieval,
+            prompt_template: payload.prompt_template,
+            language: payload.language,
+            generation,
+            agent: payload.agent,
+            follow_ups: payload.follow_ups,
+        },
+        guard.token(),
+    );
+    let events = state
+        .feedback_store
+        .clone()
+        .record(request_id.clone(), events);
 
-        let done_event = Event::default().data("[DONE]");
-        yield Ok(done_event);
+    // The guard is dropped with the stream, which cancels the generation if
+    // the client disconnects before `done`
+    let first = ChatStreamEvent::Request {
+        request_id: request_id.clone(),
     };
+    let stream = guard
+        .attach(futures::stream::once(async { first }).chain(events))
+        .map(|event| Ok(sse_event(event)));
 
-    Sse::new(stream).keep_alive(
-        axum::response::sse::KeepAlive::new()
-            .interval(Duration::from_secs(15))
-            .text("keep-alive-text"),
-    )
+    Ok((
+        [(HeaderName::from_static("x-request-id"), request_id)],
+        Sse::new(stream).keep_alive(
+            axum::response::sse::KeepAlive::new()
+                .interval(Duration::from_secs(15))
+                .text("keep-alive-text"),
+        ),
+    ))
+}
+
+// Laufende Generierung abbrechen
+async fn cancel_chat_handler(
+    State(state): State<AppState>,
+    Path(request_id): Path<String>,
+) -> StatusCode {
+    if state.generations.cancel(&request_id) {
+        StatusCode::ACCEPTED
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+// Zähler für gestartete, abgeschlossene und abgebrochene Generierungen
+async fn generation_metrics_handler(State(state): State<AppState>) -> Json<GenerationMetrics> {
+    Json(state.generations.metrics())
+}
+
+// Bewertung einer Antwort speichern
+async fn submit_feedback_handler(
+    State(state): State<AppState>,
+    Json(payload): Json<FeedbackRequest>,
+) -> Result<(StatusCode, Json<Feedback>), (StatusCode, String)> {
+    state
+        .feedback_store
+        .submit(payload)
+        .await
+        .map(|feedback| (StatusCode::CREATED, Json(feedback)))
+        .map_err(|err| match err {
+            error::RagError::InvalidRequest(message) => (StatusCode::BAD_REQUEST, message),
+            error::RagError::NotFound(message) => (StatusCode::NOT_FOUND, message),
+            err => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
+        })
+}
+
+// Alle Bewertungen als JSON Lines exportieren
+async fn export_feedback_handler(
+    State(state): State<AppState>,
+) -> Result<([(HeaderName, &'static str); 1], String), (StatusCode, String)> {
+    let lines = state
+        .feedback_store
+        .export()
+        .await
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+
+    Ok(([(header::CONTENT_TYPE, "application/x-ndjson")], lines))
+}
+
+// Auswertung der Bewertungen für die Admin-Ansicht
+async fn feedback_stats_handler(State(state): State<AppState>) -> Json<FeedbackStats> {
+    Json(state.feedback_store.stats().await)
+}
+
+fn sse_event(event: ChatStreamEvent) -> Event {
+    Event::default()
+        .event(event.name())
+        .json_data(&event)
+        .unwrap_or_else(|err| Event::default().event("error").data(err.to_string()))
+}
+
+// Ranking einer Suche Treffer für Treffer erklären
+async fn explain_search_handler(
+    State(state): State<AppState>,
+    Json(payload): Json<SearchExplainRequest>,
+) -> Result<Json<SearchExplanation>, (StatusCode, String)> {
+    let retrieval = state
+        .retrieval_service
+        .resolve_params(&payload.retrieval)
+        .map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))?;
+
+    state
+        .chat_pipeline
+        .explain(&payload.query, retrieval, payload.language)
+        .await
+        .map(Json)
+        .map_err(|err| match err {
+            error::RagError::InvalidRequest(message) => (StatusCode::BAD_REQUEST, message),
+            err => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
+        })
 }
 
 // Dokument Upload Endpoint (Multipart)
@@ -116,6 +318,7 @@ async fn upload_document_handler(
     let mut title = None;
     let mut content = String::new();
     let mut metadata = None;
+    let mut document_id = None;
 
     // Parse multipart form data
     while let Some(field) = multipart.next_field().await.unwrap() {
@@ -138,13 +341,18 @@ async fn upload_document_handler(
                     content = String::from_utf8_lossy(&content_bytes).to_string();
                 }
             }
+            // Id eines vorhandenen Dokuments, das ersetzt wird
+            Some("document_id") => {
+                if let Ok(id_bytes) = field.bytes().await {
+                    document_id = Some(String::from_utf8_lossy(&id_bytes).trim().to_string());
+                }
+            }
             Some("metadata") => {
-                if let Ok(metadata_bytes) = field.bytes().await {
-                    if let Ok(parsed_metadata) =
+                if let Ok(metadata_bytes) = field.bytes().await
+                    && let Ok(parsed_metadata) =
                         serde_json::from_slice::<models::DocumentMetadata>(&metadata_bytes)
-                    {
-                        metadata = Some(parsed_metadata);
-                    }
+                {
+                    metadata = Some(parsed_metadata);
                 }
             }
             _ => {}
@@ -155,22 +363,67 @@ async fn upload_document_handler(
         return Err((StatusCode::BAD_REQUEST, "Content is empty".to_owned()));
     }
 
-    let id = uuid::Uuid::now_v7().to_string();
+    let mut metadata = metadata.unwrap_or_default();
+    metadata
+        .language
+        .get_or_insert_with(|| rag::language::detect(&content));
+
+    let id = match document_id.filter(|document_id| !document_id.is_empty()) {
+        Some(document_id) => {
+            state
+                .meilisearch_service
+                .delete_document(&document_id)
+                .await
+                .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+            state
+                .answer_cache
+                .invalidate_documents(&[&document_id])
+                .await;
+            document_id
+        }
+        None => uuid::Uuid::now_v7().to_string(),
+    };
+
+    // Indexed anyway, retrieved chunks are screened again before generation
+    let injection_rules = match state.injection_screener.check(&content) {
+        Some(finding) => {
+            tracing::warn!(
+                target: "prompt_injection",
+                document_id = %id,
+                score = finding.score,
+                rules = ?finding.rules,
+                "Uploaded document looks like a prompt injection"
+            );
+            finding.rules
+        }
+        None => Vec::new(),
+    };
+    let created_at_timestamp = metadata
+        .created_at
+        .as_deref()
+        .and_then(|created_at| chrono::DateTime::parse_from_rfc3339(created_at).ok())
+        .map(|created_at| created_at.timestamp())
+        .unwrap_or_else(|| chrono::Utc::now().timestamp());
 
     state
         .meilisearch_service
         .index_documents(vec![InputDocument {
-            id,
+            id: id.clone(),
             title,
             content,
-            metadata,
+            metadata: Some(metadata),
             source_file: None,
+            document_id: Some(id),
+            created_at_timestamp: Some(created_at_timestamp),
+            injection_rules: injection_rules.clone(),
         }])
         .await
         .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
 
     Ok(Json(UploadResponse {
         status: "processing".to_string(),
+        flagged: !injection_rules.is_empty(),
+        injection_rules,
     }))
 }
 
@@ -178,7 +431,7 @@ async fn upload_document_handler(
 async fn list_documents_handler(
     State(state): State<AppState>,
 ) -> Result<Json<DocumentListResponse>, StatusCode> {
-    match state.meilisearch_service.list_documents().await {
+    match state.meilisearch_service.list_documents(None).await {
         Ok(documents) => Ok(Json(DocumentListResponse { documents })),
         Err(e) => {
             tracing::error!("Failed to list documents: {}", e);
@@ -186,3 +439,147 @@ async fn list_documents_handler(
         }
     }
 }
+
+// Dokument mit allen Chunks löschen
+async fn delete_document_handler(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> StatusCode {
+    if let Err(e) = state.meilisearch_service.delete_document(&id).await {
+        tracing::error!("Failed to delete document {}: {}", id, e);
+        return StatusCode::INTERNAL_SERVER_ERROR;
+    }
+    state.answer_cache.invalidate_documents(&[&id]).await;
+
+    StatusCode::NO_CONTENT
+}
+
+// Dokument per Map-Reduce über seine Chunks zusammenfassen (Streaming)
+async fn summarize_document_handler(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Sse<impl futures::Stream<Item = Result<Event, Infallible>>>, (StatusCode, String)> {
+    let chunks = state
+        .meilisearch_service
+        .document_chunks(&id, None)
+        .await
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+    if chunks.is_empty() {
+        return Err((
+            StatusCode::NOT_FOUND,
+            format!("Document '{}' not found", id),
+        ));
+    }
+
+    let events = state.document_summarizer.clone().summarize(id, chunks);
+    let stream = async_stream::stream! {
+        for await event in events {
+            yield Ok(summary_event(event));
+        }
+    };
+
+    Ok(Sse::new(stream).keep_alive(
+        axum::response::sse::KeepAlive::new()
+            .interval(Duration::from_secs(15))
+            .text("keep-alive-text"),
+    ))
+}
+
+fn summary_event(event: SummaryStreamEvent) -> Event {
+    Event::default()
+        .event(event.name())
+        .json_data(&event)
+        .unwrap_or_else(|err| Event::default().event("error").data(err.to_string()))
+}
+
+// Prompt-Templates auflisten
+async fn list_prompts_handler(State(state): State<AppState>) -> Json<PromptTemplateListResponse> {
+    Json(PromptTemplateListResponse {
+        templates: state.prompt_templates.names(),
+    })
+}
+
+// Prompt-Templates neu laden
+async fn reload_prompts_handler(
+    State(state): State<AppState>,
+) -> Result<Json<PromptTemplateListResponse>, (StatusCode, String)> {
+    let templates = state
+        .prompt_templates
+        .reload()
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+
+    Ok(Json(PromptTemplateListResponse { templates }))
+}
+
+// Gerenderten Prompt für eine Chat-Anfrage anzeigen, ohne das LLM aufzurufen
+async fn preview_prompt_handler(
+    State(state): State<AppState>,
+    Json(payload): Json<ChatRequest>,
+) -> Result<Json<PromptPreview>, (StatusCode, String)> {
+    let retrieval = state
+        .retrieval_service
+        .resolve_params(&payload.retrieval)
+        .map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))?;
+    state
+        .prompt_templates
+        .validate(
+            payload.prompt_template.as_deref(),
+            payload
+                .language
+                .unwrap_or_else(|| language::detect(&payload.message)),
+        )
+        .map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))?;
+    let generation = state
+        .generation_service
+        .resolve_params(&payload.generation)
+        .map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))?;
+
+    state
+        .chat_pipeline
+        .preview(ChatTurn {
+            message: payload.message,
+            session_id: payload.session_id,
+            history: Vec::new(),
+            retrieval,
+            prompt_template: payload.prompt_template,
+            language: payload.language,
+            generation,
+            agent: payload.agent,
+            follow_ups: None,
+        })
+        .await
+        .map(Json)
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))
+}
+
+// Chat-Sessions auflisten
+async fn list_sessions_handler(State(state): State<AppState>) -> Json<SessionListResponse> {
+    Json(SessionListResponse {
+        sessions: state.session_store.list().await,
+    })
+}
+
+// Chat-Session mit Verlauf und Quellen abrufen
+async fn get_session_handler(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<Session>, StatusCode> {
+    state
+        .session_store
+        .get(&id)
+        .await
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+// Chat-Session löschen
+async fn delete_session_handler(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> StatusCode {
+    if state.session_store.delete(&id).await {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
@@ -8,6 +8,35 @@ pub struct DocumentMetadata {
     pub document_type: Option<String>,
     pub created_at: Option<String>,
     pub updated_at: Option<String>,
+    /// Detected at upload unless given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<Language>,
+}
+
+/// Languages of the knowledge base.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Language {
+    De,
+    En,
+}
+
+impl Language {
+    /// ISO 639-1 code, as stored in `metadata.language`
+    pub fn code(self) -> &'static str {
+        match self {
+            Language::De => "de",
+            Language::En => "en",
+        }
+    }
+
+    /// English name, as used in prompts
+    pub fn name(self) -> &'static str {
+        match self {
+            Language::De => "German",
+            Language::En => "English",
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -17,9 +46,19 @@ pub struct InputDocument {
     pub content: String,
     pub metadata: Option<DocumentMetadata>,
     pub source_file: Option<String>,
+    /// Id of the uploaded document the record belongs to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub document_id: Option<String>,
+    /// `metadata.created_at` as unix timestamp, for date range filters
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at_timestamp: Option<i64>,
+    /// Prompt injection rules the document matched at upload
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub injection_rules: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[allow(dead_code)] // used by the ingestion pipeline (not wired yet)
 pub struct Document {
     pub id: String,
     pub title: String,
@@ -35,12 +74,115 @@ pub struct SearchResult {
     pub content: String,
     pub metadata: DocumentMetadata,
     pub source_file: Option<String>,
+    /// Id of the document a chunk belongs to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub document_id: Option<String>,
+    /// Position of the chunk within its document
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunk_index: Option<usize>,
+    /// Cross-encoder score, set when the result went through the reranker
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rerank_score: Option<f32>,
+    /// Meilisearch ranking score (0.0 - 1.0) of the hybrid search
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ranking_score: Option<f64>,
+    /// Ids of the adjacent or contained chunks of the same document that the
+    /// context builder merged into this source
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub merged_chunk_ids: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChatRequest {
     pub message: String,
+    /// Chosen by the client; without one the turn is not stored
     pub session_id: Option<String>,
+    #[serde(flatten)]
+    pub retrieval: RetrievalOptions,
+    /// Prompt template name, by default chosen by the `document_type` of the best source
+    pub prompt_template: Option<String>,
+    /// Answer language, detected from the message if not set
+    pub language: Option<Language>,
+    #[serde(flatten)]
+    pub generation: GenerationOptions,
+    /// Let the model search the knowledge base itself through tool calls.
+    /// Bypasses the answer cache and the relevance threshold, `follow_ups`
+    /// still applies
+    #[serde(default)]
+    pub agent: bool,
+    /// Suggest follow-up questions after the answer, `FOLLOW_UP_QUESTIONS` if not set
+    pub follow_ups: Option<bool>,
+}
+
+/// Per-request generation overrides, checked against the server configuration.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GenerationOptions {
+    /// One of `LLM_ALLOWED_MODELS`
+    pub model: Option<String>,
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    pub max_tokens: Option<u32>,
+    pub stop: Option<Vec<String>>,
+    pub seed: Option<i64>,
+}
+
+/// Effective generation parameters of a chat turn.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GenerationParams {
+    pub model: String,
+    pub temperature: f32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    pub max_tokens: u32,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stop: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+}
+
+/// Per-request retrieval controls.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RetrievalOptions {
+    #[serde(default)]
+    pub retrieval_mode: RetrievalMode,
+    /// Number of chunks to retrieve
+    pub top_k: Option<usize>,
+    /// Weight of the semantic search in the hybrid search (0.0 = keyword only, 1.0 = semantic only)
+    pub semantic_ratio: Option<f32>,
+    /// Raw Meilisearch filter expression
+    pub filter: Option<String>,
+    pub filters: Option<StructuredFilters>,
+    /// Only answer from these documents
+    pub document_ids: Option<Vec<String>>,
+    /// Number of chunks before and after each hit to add from its document
+    pub neighbor_chunks: Option<usize>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StructuredFilters {
+    /// Matches chunks having any of the tags
+    pub tags: Option<Vec<String>>,
+    pub author: Option<String>,
+    pub document_type: Option<String>,
+    pub language: Option<Language>,
+    /// RFC 3339 timestamp or `YYYY-MM-DD`
+    pub created_after: Option<String>,
+    /// RFC 3339 timestamp or `YYYY-MM-DD`
+    pub created_before: Option<String>,
+}
+
+/// Strategy used by the `RetrievalService` to find relevant chunks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RetrievalMode {
+    /// A single hybrid search with the query
+    #[default]
+    Standard,
+    /// LLM-generated paraphrases searched concurrently and merged via reciprocal rank fusion
+    MultiQuery,
+    /// Hypothetical document embedding: the semantic search uses an LLM-drafted
+    /// answer passage, the keyword search the original query
+    Hyde,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -50,7 +192,189 @@ pub struct ChatResponse {
     pub session_id: Option<String>,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChatRole {
+    User,
+    Assistant,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub role: ChatRole,
+    pub content: String,
+    /// Sources the answer was generated from (empty for user messages)
+    pub sources: Vec<SearchResult>,
+    #[serde(default)]
+    pub citations: Vec<Citation>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// A `[Source N]` marker in a generated answer, resolved against the sources
+/// that were given to the LLM.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Citation {
+    /// The marker as it appears in the answer, e.g. `[Source 2]`
+    pub marker: String,
+    pub source_number: usize,
+    /// The sentence the marker is attached to
+    pub statement: String,
+    pub source_id: Option<String>,
+    pub title: Option<String>,
+    pub source_file: Option<String>,
+    /// `false` if the answer cites a source number that was not in the context
+    pub valid: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    pub id: String,
+    pub title: String,
+    pub messages: Vec<ChatMessage>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionSummary {
+    pub id: String,
+    pub title: String,
+    pub message_count: usize,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PromptTemplateListResponse {
+    pub templates: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionListResponse {
+    pub sessions: Vec<SessionSummary>,
+}
+
+/// Events emitted on the `/api/chat` SSE stream.
+///
+/// The event name (see [`ChatStreamEvent::name`]) is sent as the SSE `event` field,
+/// the variant's fields as JSON `data`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum ChatStreamEvent {
+    /// The server-generated id of the turn, sent first
+    Request {
+        request_id: String,
+    },
+    /// The standalone query that was actually sent to retrieval
+    Query {
+        query: String,
+    },
+    Sources {
+        sources: Vec<SearchResult>,
+    },
+    Token {
+        content: String,
+    },
+    Error {
+        message: String,
+    },
+    /// A tool call requested by the model in agent mode
+    ToolCall {
+        id: String,
+        name: String,
+        arguments: serde_json::Value,
+    },
+    /// The result of a tool call as passed back to the model
+    ToolResult {
+        id: String,
+        name: String,
+        result: String,
+        /// Sources the result introduced, numbered after the earlier ones
+        sources: Vec<SearchResult>,
+    },
+    /// Questions to ask next, sent after the answer
+    FollowUps {
+        questions: Vec<String>,
+    },
+    Done {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        session_id: Option<String>,
+        citations: Vec<Citation>,
+        /// Parameters the answer was generated with, absent if the LLM was not called
+        #[serde(skip_serializing_if = "Option::is_none")]
+        generation: Option<GenerationParams>,
+        /// The generation was cancelled, the answer is incomplete
+        #[serde(skip_serializing_if = "std::ops::Not::not")]
+        cancelled: bool,
+        /// The answer was cut off at `max_tokens`
+        #[serde(skip_serializing_if = "std::ops::Not::not")]
+        truncated: bool,
+        /// The answer was served from the answer cache
+        #[serde(skip_serializing_if = "std::ops::Not::not")]
+        cached: bool,
+    },
+}
+
+impl ChatStreamEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChatStreamEvent::Request { .. } => "request",
+            ChatStreamEvent::Query { .. } => "query",
+            ChatStreamEvent::Sources { .. } => "sources",
+            ChatStreamEvent::Token { .. } => "token",
+            ChatStreamEvent::Error { .. } => "error",
+            ChatStreamEvent::ToolCall { .. } => "tool_call",
+            ChatStreamEvent::ToolResult { .. } => "tool_result",
+            ChatStreamEvent::FollowUps { .. } => "follow_ups",
+            ChatStreamEvent::Done { .. } => "done",
+        }
+    }
+}
+
+/// Step of a document summary, see [`SummaryStreamEvent::Progress`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SummaryStage {
+    /// Summarizing groups of consecutive chunks
+    Map,
+    /// Combining the summaries of the previous step
+    Reduce,
+}
+
+/// Event of the summary stream of `POST /api/documents/{id}/summarize`,
+/// sent like [`ChatStreamEvent`].
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum SummaryStreamEvent {
+    /// A group of the current step was summarized
+    Progress {
+        stage: SummaryStage,
+        completed: usize,
+        total: usize,
+    },
+    Summary {
+        document_id: String,
+        summary: String,
+        /// The summary was stored with the document by an earlier request
+        cached: bool,
+    },
+    Error {
+        message: String,
+    },
+}
+
+impl SummaryStreamEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SummaryStreamEvent::Progress { .. } => "progress",
+            SummaryStreamEvent::Summary { .. } => "summary",
+            SummaryStreamEvent::Error { .. } => "error",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[allow(dead_code)] // JSON counterpart of the multipart upload
 pub struct UploadRequest {
     pub title: String,
     pub content: String,
@@ -60,9 +384,15 @@ pub struct UploadRequest {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UploadResponse {
     pub status: String,
+    /// The document looks like a prompt injection, see `injection_rules`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub flagged: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub injection_rules: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[allow(dead_code)] // used by the ingestion pipeline (not wired yet)
 pub struct TaskStatus {
     pub id: String,
     pub status: TaskStatusType,
@@ -73,6 +403,7 @@ pub struct TaskStatus {
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[allow(dead_code)] // used by the ingestion pipeline (not wired yet)
 pub enum TaskStatusType {
     Pending,
     Processing,
@@ -93,6 +424,172 @@ pub struct DocumentListResponse {
     pub documents: Vec<DocumentInfo>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchExplainRequest {
+    pub query: String,
+    #[serde(flatten)]
+    pub retrieval: RetrievalOptions,
+    /// Language to boost, detected from the query if not set
+    pub language: Option<Language>,
+}
+
+/// Meilisearch's explanation of a single hit.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HitDetails {
+    /// `_rankingScoreDetails` as returned by Meilisearch
+    pub ranking_score_details: Option<serde_json::Map<String, serde_json::Value>>,
+    /// Query terms found in title or content
+    pub matched_terms: Vec<String>,
+}
+
+/// How a hit of the hybrid search ranked and what each later stage did to it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HitExplanation {
+    pub id: String,
+    pub title: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub document_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunk_index: Option<usize>,
+    /// 1-based position in the hybrid search
+    pub search_position: usize,
+    pub ranking_score: Option<f64>,
+    /// Score in a pure keyword search, `None` if the hit is not among its results
+    pub keyword_score: Option<f64>,
+    /// Score in a pure semantic search, `None` if the hit is not among its results
+    pub semantic_score: Option<f64>,
+    /// Approximation: `keyword_score` weighted with `1 - semantic_ratio`, `None`
+    /// if the hit is not among the keyword results. Meilisearch does not combine
+    /// the scores this way, `ranking_score_details` tells how it ranked the hit.
+    pub keyword_contribution: Option<f64>,
+    /// Approximation: `semantic_score` weighted with `semantic_ratio`, `None` if
+    /// the hit is not among the semantic results
+    pub semantic_contribution: Option<f64>,
+    #[serde(flatten)]
+    pub details: HitDetails,
+    /// The chunk is in the boosted answer language
+    pub language_boosted: bool,
+    /// 1-based position after the language boost
+    pub boosted_position: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rerank_score: Option<f32>,
+    /// 1-based position after reranking, `None` if the reranker dropped the hit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rerank_position: Option<usize>,
+    /// 1-based position in the retrieval result, `None` if the hit was dropped
+    pub final_position: Option<usize>,
+    pub outcome: HitOutcome,
+    /// Set if the prompt injection screening flagged the hit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub screening: Option<HitScreening>,
+    /// The hit is a source in the LLM context or merged into one
+    pub in_context: bool,
+}
+
+/// What the prompt injection screening did to a hit within `top_k`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HitScreening {
+    pub action: ScreeningOutcome,
+    pub score: f64,
+    /// Names of the rules that matched
+    pub rules: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScreeningOutcome {
+    /// Left out of the context
+    Quarantined,
+    /// Passed on with the matched text removed
+    Neutralized,
+    /// Only logged, `INJECTION_SCREENING=off`
+    Logged,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HitOutcome {
+    Kept,
+    /// Dropped for scoring below `RERANKER_SCORE_THRESHOLD`
+    BelowRerankThreshold,
+    /// Ranked below `top_k`
+    CutByTopK,
+    /// Withheld by the prompt injection screening
+    Quarantined,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchExplanation {
+    pub query: String,
+    pub semantic_ratio: f32,
+    pub top_k: usize,
+    /// Number of candidates fetched from Meilisearch
+    pub candidates: usize,
+    pub reranker_enabled: bool,
+    /// `RELEVANCE_SCORE_THRESHOLD` of the chat pipeline
+    pub relevance_threshold: f64,
+    /// Best ranking score among the retrieved hits
+    pub top_score: Option<f64>,
+    /// The chat would answer without calling the LLM, as no hit reaches the threshold
+    pub abstained: bool,
+    /// Always set: `keyword_contribution` and `semantic_contribution` of the hits
+    /// are estimated from separate keyword and semantic searches
+    pub contributions_approximate: bool,
+    pub hits: Vec<HitExplanation>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FeedbackRating {
+    Up,
+    Down,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeedbackRequest {
+    /// `X-Request-Id` of the chat answer, or the completion id for `/v1/chat/completions`
+    pub request_id: String,
+    pub rating: FeedbackRating,
+    pub comment: Option<String>,
+    /// Id of the source that was wrong or misleading, one of the answer's chunks
+    pub wrong_source_id: Option<String>,
+}
+
+/// Stored feedback, linked to the answer it rates.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Feedback {
+    pub id: String,
+    pub request_id: String,
+    pub rating: FeedbackRating,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wrong_source_id: Option<String>,
+    /// The standalone query that was sent to retrieval
+    pub query: String,
+    pub chunk_ids: Vec<String>,
+    pub answer: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeedbackStats {
+    pub total: usize,
+    pub up: usize,
+    pub down: usize,
+    pub with_comment: usize,
+    /// Sources reported as wrong, most reported first
+    pub wrong_sources: Vec<SourceFeedbackCount>,
+    /// Chunks of down-rated answers, most frequent first
+    pub down_rated_chunks: Vec<SourceFeedbackCount>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SourceFeedbackCount {
+    pub source_id: String,
+    pub count: usize,
+}
+
 // // Task management
 // pub type TaskStore = std::sync::Arc<std::sync::RwLock<HashMap<String, TaskStatus>>>;
 
@@ -0,0 +1,452 @@
+//! OpenAI-compatible facade (`/v1/models`, `/v1/chat/completions`) over the RAG
+//! pipeline, so that any OpenAI client can use the knowledge base as a model.
+//!
+//! Retrieved sources and verified citations are returned in the non-standard
+//! `sources` and `citations` fields.
+
+use crate::AppState;
+use crate::models::{
+    ChatMessage, ChatRole, ChatStreamEvent, Citation, GenerationOptions, Language,
+    RetrievalOptions, SearchResult,
+};
+use crate::rag::{ChatTurn, language};
+use crate::services::GenerationGuard;
+use axum::{
+    Json,
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response, Sse, sse::Event},
+};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+
+/// Model id under which the RAG pipeline is exposed.
+const MODEL_ID: &str = "rag-assistant";
+
+#[derive(Debug, Deserialize)]
+pub struct ChatCompletionRequest {
+    pub model: String,
+    pub messages: Vec<RequestMessage>,
+    #[serde(default)]
+    pub stream: bool,
+    #[serde(default)]
+    pub temperature: Option<f32>,
+    #[serde(default)]
+    pub top_p: Option<f32>,
+    #[serde(default, alias = "max_completion_tokens")]
+    pub max_tokens: Option<u32>,
+    #[serde(default)]
+    pub stop: Option<StopSequences>,
+    #[serde(default)]
+    pub seed: Option<i64>,
+    /// Extension: retrieval controls as in `/api/chat`
+    #[serde(flatten)]
+    pub retrieval: RetrievalOptions,
+    /// Extension: prompt template as in `/api/chat`
+    #[serde(default)]
+    pub prompt_template: Option<String>,
+    /// Extension: answer language as in `/api/chat`
+    #[serde(default)]
+    pub language: Option<Language>,
+    /// Extension: agent mode as in `/api/chat`
+    #[serde(default)]
+    pub agent: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum StopSequences {
+    Single(String),
+    Multiple(Vec<String>),
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RequestMessage {
+    pub role: String,
+    #[serde(default)]
+    pub content: Option<MessageContent>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum MessageContent {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ContentPart {
+    #[serde(default)]
+    pub text: Option<String>,
+}
+
+impl MessageContent {
+    fn text(&self) -> String {
+        match self {
+            MessageContent::Text(text) => text.to_owned(),
+            MessageContent::Parts(parts) => parts
+                .iter()
+                .filter_map(|part| part.text.as_deref())
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ChatCompletionResponse {
+    id: String,
+    object: &'static str,
+    created: i64,
+    model: String,
+    choices: Vec<Choice>,
+    sources: Vec<SearchResult>,
+    citations: Vec<Citation>,
+}
+
+#[derive(Debug, Serialize)]
+struct Choice {
+    index: u32,
+    message: ResponseMessage,
+    finish_reason: Option<&'static str>,
+}
+
+#[derive(Debug, Serialize)]
+struct ResponseMessage {
+    role: &'static str,
+    content: String,
+}
+
+#[derive(Debug, Serialize)]
+struct ChatCompletionChunk {
+    id: String,
+    object: &'static str,
+    created: i64,
+    model: String,
+    choices: Vec<ChunkChoice>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sources: Option<Vec<SearchResult>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    citations: Option<Vec<Citation>>,
+}
+
+#[derive(Debug, Serialize)]
+struct ChunkChoice {
+    index: u32,
+    delta: Delta,
+    finish_reason: Option<&'static str>,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct Delta {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    role: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct ModelList {
+    object: &'static str,
+    data: Vec<Model>,
+}
+
+#[derive(Debug, Serialize)]
+struct Model {
+    id: &'static str,
+    object: &'static str,
+    created: i64,
+    owned_by: &'static str,
+}
+
+fn error_response(status: StatusCode, kind: &str, message: &str) -> Response {
+    (
+        status,
+        Json(serde_json::json!({
+            "error": { "message": message, "type": kind, "code": null }
+        })),
+    )
+        .into_response()
+}
+
+// Verfügbare Modelle (OpenAI-kompatibel)
+pub async fn list_models_handler() -> impl IntoResponse {
+    Json(ModelList {
+        object: "list",
+        data: vec![Model {
+            id: MODEL_ID,
+            object: "model",
+            created: 0,
+            owned_by: "rag-assistant",
+        }],
+    })
+}
+
+// Chat Completions (OpenAI-kompatibel) über die RAG Pipeline, mit und ohne Streaming
+pub async fn chat_completions_handler(
+    State(state): State<AppState>,
+    Json(request): Json<ChatCompletionRequest>,
+) -> Response {
+    if request.model != MODEL_ID {
+        return error_response(
+            StatusCode::NOT_FOUND,
+            "invalid_request_error",
+            &format!("The model '{}' does not exist", request.model),
+        );
+    }
+
+    let retrieval = match state.retrieval_service.resolve_params(&request.retrieval) {
+        Ok(retrieval) => retrieval,
+        Err(err) => {
+            return error_response(
+                StatusCode::BAD_REQUEST,
+                "invalid_request_error",
+                &err.to_string(),
+            );
+        }
+    };
+
+    // `model` selects the RAG pipeline, the LLM model stays the configured default
+    let options = GenerationOptions {
+        model: None,
+        temperature: request.temperature,
+        top_p: request.top_p,
+        max_tokens: request.max_tokens,
+        stop: request.stop.map(|stop| match stop {
+            StopSequences::Single(stop) => vec![stop],
+            StopSequences::Multiple(stop) => stop,
+        }),
+        seed: request.seed,
+    };
+    let generation = match state.generation_service.resolve_params(&options) {
+        Ok(generation) => generation,
+        Err(err) => {
+            return error_response(
+                StatusCode::BAD_REQUEST,
+                "invalid_request_error",
+                &err.to_string(),
+            );
+        }
+    };
+
+    let (message, history) = match split_messages(request.messages) {
+        Ok(split) => split,
+        Err(message) => {
+            return error_response(StatusCode::BAD_REQUEST, "invalid_request_error", message);
+        }
+    };
+    let language = request
+        .language
+        .unwrap_or_else(|| language::detect(&message));
+    if let Err(err) = state
+        .prompt_templates
+        .validate(request.prompt_template.as_deref(), language)
+    {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            "invalid_request_error",
+            &err.to_string(),
+        );
+    }
+    let turn = ChatTurn {
+        message,
+        session_id: None,
+        history,
+        retrieval,
+        prompt_template: request.prompt_template,
+        language: request.language,
+        generation,
+        agent: request.agent,
+        // Not part of the OpenAI format
+        follow_ups: Some(false),
+    };
+
+    let id = format!("chatcmpl-{}", uuid::Uuid::now_v7().simple());
+    let created = chrono::Utc::now().timestamp();
+    // The completion id doubles as request id for `/api/chat/{request_id}/cancel`
+    let guard = state.generations.register(id.clone());
+    let events = state.chat_pipeline.clone().run(turn, guard.token());
+    let events = state.feedback_store.clone().record(id.clone(), events);
+
+    if request.stream {
+        stream_completion(id, created, request.model, events, guard).into_response()
+    } else {
+        collect_completion(id, created, request.model, events, guard).await
+    }
+}
+
+/// Splits the OpenAI message list into the latest user question and the
+/// preceding conversation. System and tool messages are ignored, the RAG
+/// pipeline uses its own system prompt.
+fn split_messages(
+    messages: Vec<RequestMessage>,
+) -> Result<(String, Vec<ChatMessage>), &'static str> {
+    let mut history: Vec<ChatMessage> = messages
+        .into_iter()
+        .filter_map(|message| {
+            let role = match message.role.as_str() {
+                "user" => ChatRole::User,
+                "assistant" => ChatRole::Assistant,
+                _ => return None,
+            };
+            Some(ChatMessage {
+                role,
+                content: message.content.map(|c| c.text()).unwrap_or_default(),
+                sources: Vec::new(),
+                citations: Vec::new(),
+                created_at: chrono::Utc::now(),
+            })
+        })
+        .collect();
+
+    match history.pop() {
+        Some(last) if last.role == ChatRole::User && !last.content.trim().is_empty() => {
+            Ok((last.content, history))
+        }
+        _ => Err("The last message must be a non-empty user message"),
+    }
+}
+
+async fn collect_completion(
+    id: String,
+    created: i64,
+    model: String,
+    events: impl futures::Stream<Item = ChatStreamEvent>,
+    guard: GenerationGuard,
+) -> Response {
+    let mut events = std::pin::pin!(events);
+    let mut content = String::new();
+    let mut sources = Vec::new();
+    let mut citations = Vec::new();
+    let mut finish = None;
+
+    while let Some(event) = events.next().await {
+        match event {
+            ChatStreamEvent::Sources { sources: retrieved } => sources = retrieved,
+            ChatStreamEvent::ToolResult {
+                sources: retrieved, ..
+            } => sources.extend(retrieved),
+            ChatStreamEvent::Token { content: token } => content.push_str(&token),
+            ChatStreamEvent::Error { message } => {
+                guard.finish();
+                return error_response(StatusCode::BAD_GATEWAY, "server_error", &message);
+            }
+            ChatStreamEvent::Done {
+                citations: verified,
+                cancelled,
+                truncated,
+                ..
+            } => {
+                citations = verified;
+                finish = finish_reason(cancelled, truncated);
+            }
+            ChatStreamEvent::Request { .. }
+            | ChatStreamEvent::Query { .. }
+            | ChatStreamEvent::ToolCall { .. }
+            | ChatStreamEvent::FollowUps { .. } => {}
+        }
+    }
+    guard.finish();
+
+    Json(ChatCompletionResponse {
+        id,
+        object: "chat.completion",
+        created,
+        model,
+        choices: vec![Choice {
+            index: 0,
+            message: ResponseMessage {
+                role: "assistant",
+                content,
+            },
+            finish_reason: finish,
+        }],
+        sources,
+        citations,
+    })
+    .into_response()
+}
+
+fn stream_completion(
+    id: String,
+    created: i64,
+    model: String,
+    events: impl futures::Stream<Item = ChatStreamEvent> + Send + 'static,
+    guard: GenerationGuard,
+) -> Sse<impl futures::Stream<Item = Result<Event, Infallible>>> {
+    let chunk = move |delta: Delta,
+                      finish_reason: Option<&'static str>,
+                      sources: Option<Vec<SearchResult>>,
+                      citations: Option<Vec<Citation>>| {
+        let chunk = ChatCompletionChunk {
+            id: id.clone(),
+            object: "chat.completion.chunk",
+            created,
+            model: model.clone(),
+            choices: vec![ChunkChoice {
+                index: 0,
+                delta,
+                finish_reason,
+            }],
+            sources,
+            citations,
+        };
+        Event::default()
+            .json_data(chunk)
+            .unwrap_or_else(|err| Event::default().data(err.to_string()))
+    };
+
+    let stream = async_stream::stream! {
+        // Strict clients expect the role before any content, also in agent mode
+        let delta = Delta { role: Some("assistant"), content: None };
+        yield Ok(chunk(delta, None, None, None));
+
+        for await event in events {
+            match event {
+                ChatStreamEvent::Sources { sources } => {
+                    yield Ok(chunk(Delta::default(), None, Some(sources), None));
+                }
+                // Agent mode: sources arrive with each tool result
+                ChatStreamEvent::ToolResult { sources, .. } if !sources.is_empty() => {
+                    yield Ok(chunk(Delta::default(), None, Some(sources), None));
+                }
+                ChatStreamEvent::Token { content } => {
+                    let delta = Delta { role: None, content: Some(content) };
+                    yield Ok(chunk(delta, None, None, None));
+                }
+                ChatStreamEvent::Error { message } => {
+                    let error = serde_json::json!({
+                        "error": { "message": message, "type": "server_error", "code": null }
+                    });
+                    yield Ok(Event::default().data(error.to_string()));
+                }
+                ChatStreamEvent::Done { citations, cancelled, truncated, .. } => {
+                    let finish = finish_reason(cancelled, truncated);
+                    yield Ok(chunk(Delta::default(), finish, None, Some(citations)));
+                }
+                ChatStreamEvent::Request { .. }
+                | ChatStreamEvent::Query { .. }
+                | ChatStreamEvent::ToolCall { .. }
+                | ChatStreamEvent::ToolResult { .. }
+                | ChatStreamEvent::FollowUps { .. } => {}
+            }
+        }
+        yield Ok(Event::default().data("[DONE]"));
+        guard.finish();
+    };
+
+    Sse::new(stream)
+}
+
+/// `length` if the answer was cut off at `max_tokens`. OpenAI has no finish
+/// reason for a cancelled completion, it ends with `null`.
+fn finish_reason(cancelled: bool, truncated: bool) -> Option<&'static str> {
+    if truncated {
+        Some("length")
+    } else if cancelled {
+        None
+    } else {
+        Some("stop")
+    }
+}
@@ -0,0 +1,414 @@
+use crate::models::{ChatStreamEvent, SearchResult};
+use crate::rag::context::format_source_block;
+use crate::rag::prompts::AGENT_TEMPLATE;
+use crate::rag::{
+    ChatTurn, GenerationService, PromptVariables, RetrievalParams, RetrievalService, citations,
+    language,
+};
+use crate::services::SessionStore;
+use crate::services::llm::{
+    LlmMessage, LlmRole, LlmService, StreamToken, ToolCall, ToolDefinition,
+};
+use futures::{Stream, StreamExt};
+use serde::Deserialize;
+use serde_json::json;
+use std::env;
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
+
+/// Upper bound for the `top_k` argument of the search tool.
+const MAX_SEARCH_TOP_K: usize = 20;
+
+/// Documents returned by `fetch_document` are cut off after this many characters.
+const MAX_DOCUMENT_CHARS: usize = 12_000;
+
+/// Number of documents listed by `list_documents`.
+const MAX_LISTED_DOCUMENTS: usize = 100;
+
+/// Sent after the last tool round when the model is still calling tools.
+const FINAL_ANSWER_INSTRUCTION: &str =
+    "The tool budget is exhausted. Answer the question now with the information gathered so far.";
+
+#[derive(Deserialize)]
+struct SearchArguments {
+    query: String,
+    top_k: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct FetchDocumentArguments {
+    document_id: String,
+}
+
+/// Agent mode of the chat: instead of a single retrieval before generation,
+/// the model searches the knowledge base itself through function calling.
+///
+/// Runs at most `AGENT_MAX_STEPS` (default 4) tool rounds; each tool call and
+/// its result are emitted as [`ChatStreamEvent`]s before the final answer.
+pub struct ChatAgent {
+    retrieval_service: Arc<RetrievalService>,
+    generation_service: Arc<GenerationService>,
+    session_store: Arc<SessionStore>,
+    max_steps: usize,
+}
+
+impl ChatAgent {
+    pub fn new(
+        retrieval_service: Arc<RetrievalService>,
+        generation_service: Arc<GenerationService>,
+        session_store: Arc<SessionStore>,
+    ) -> Self {
+        let max_steps = env::var("AGENT_MAX_STEPS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(4);
+
+        Self {
+            retrieval_service,
+            generation_service,
+            session_store,
+            max_steps,
+        }
+    }
+
+    /// Runs a chat turn in agent mode. The turn's retrieval parameters apply to
+    /// every search; without a requested template, the built-in `agent`
+    /// template is used.
+    pub fn run(
+        self: Arc<Self>,
+        turn: ChatTurn,
+        cancel: CancellationToken,
+    ) -> impl Stream<Item = ChatStreamEvent> + use<> {
+        async_stream::stream! {
+            let ChatTurn { message, session_id, history, mut retrieval, prompt_template, language, generation, .. } = turn;
+            let language = language.unwrap_or_else(|| language::detect(&message));
+            retrieval.boost_language = Some(language);
+
+            let history = match &session_id {
+                Some(session_id) => self.session_store.history(session_id).await,
+                None => history,
+            };
+
+            let prompt = match self.generation_service.render_prompt(
+                Some(prompt_template.as_deref().unwrap_or(AGENT_TEMPLATE)),
+                &PromptVariables {
+                    context: "",
+                    question: &message,
+                    history: &history,
+                    language,
+                    sources: &[],
+                },
+            ) {
+                Ok(prompt) => prompt,
+                Err(err) => {
+                    tracing::error!("Prompt rendering failed: {}", err);
+                    yield ChatStreamEvent::Error { message: err.to_string() };
+                    yield ChatStreamEvent::Done { session_id, citations: Vec::new(), generation: None, cancelled: false, truncated: false, cached: false };
+                    return;
+                }
+            };
+
+            let tools = tool_definitions();
+            let mut messages = LlmService::build_messages(&prompt.system, &prompt.history, &prompt.user);
+            let mut sources: Vec<SearchResult> = Vec::new();
+            let mut answer = None;
+            let mut truncated = false;
+
+            'steps: for step in 0..self.max_steps {
+                let reply = tokio::select! {
+                    biased;
+                    _ = cancel.cancelled() => break,
+                    reply = self.generation_service.chat_with_tools(messages.clone(), &tools, &generation) => reply,
+                };
+                let reply = match reply {
+                    Ok(reply) => reply,
+                    Err(err) => {
+                        tracing::error!("Agent step {} failed: {}", step + 1, err);
+                        yield ChatStreamEvent::Error { message: err.to_string() };
+                        answer = Some(String::new());
+                        break;
+                    }
+                };
+
+                if reply.tool_calls.is_empty() {
+                    if !reply.content.is_empty() {
+                        yield ChatStreamEvent::Token { content: reply.content.clone() };
+                    }
+                    truncated = reply.truncated;
+                    answer = Some(reply.content);
+                    break;
+                }
+
+                let calls = reply.tool_calls.clone();
+                messages.push(reply.into_message());
+                for call in calls {
+                    tracing::info!("Agent step {}: calling {} with {}", step + 1, call.name, call.arguments);
+                    yield ChatStreamEvent::ToolCall {
+                        id: call.id.clone(),
+                        name: call.name.clone(),
+                        arguments: call.arguments.clone(),
+                    };
+
+                    let known_sources = sources.len();
+                    let result = tokio::select! {
+                        biased;
+                        _ = cancel.cancelled() => break 'steps,
+                        result = self.execute(&call, &retrieval, &mut sources) => result,
+                    };
+                    yield ChatStreamEvent::ToolResult {
+                        id: call.id.clone(),
+                        name: call.name.clone(),
+                        result: result.clone(),
+                        sources: sources[known_sources..].to_vec(),
+                    };
+                    messages.push(LlmMessage::tool_result(&call.id, result));
+                }
+            }
+
+            let answer = match answer {
+                Some(answer) => answer,
+                None if cancel.is_cancelled() => String::new(),
+                None => {
+                    tracing::info!("Agent used all {} steps, generating final answer", self.max_steps);
+                    messages.push(LlmMessage::new(LlmRole::User, FINAL_ANSWER_INSTRUCTION));
+
+                    let mut answer = String::new();
+                    match self.generation_service.stream_messages(messages, &generation).await {
+                        Ok(mut tokens) => {
+                            while let Some(token) = tokio::select! {
+                                biased;
+                                _ = cancel.cancelled() => None,
+                                token = tokens.next() => token,
+                            } {
+                                match token {
+                                    Ok(StreamToken::Text(content)) if content.is_empty() => {}
+                                    Ok(StreamToken::Text(content)) => {
+                                        answer.push_str(&content);
+                                        yield ChatStreamEvent::Token { content };
+                                    }
+                                    Ok(StreamToken::Truncated) => truncated = true,
+                                    Err(err) => {
+                                        tracing::error!("Generation failed mid-stream: {}", err);
+                                        yield ChatStreamEvent::Error { message: err.to_string() };
+                                        break;
+                                    }
+                                }
+                            }
+                        }
+                        Err(err) => {
+                            tracing::error!("Generation failed: {}", err);
+                            yield ChatStreamEvent::Error { message: err.to_string() };
+                        }
+                    }
+                    answer
+                }
+            };
+
+            let cancelled = cancel.is_cancelled();
+            if cancelled {
+                tracing::info!("Agent cancelled after {} characters", answer.len());
+            }
+
+            let citations = citations::extract_citations(&answer, &sources);
+
+            if let Some(session_id) = &session_id
+                && !answer.is_empty()
+            {
+                self.session_store
+                    .append_turn(session_id, &message, &answer, sources, citations.clone())
+                    .await;
+            }
+
+            yield ChatStreamEvent::Done { session_id, citations, generation: Some(generation), cancelled, truncated, cached: false };
+        }
+    }
+
+    /// Executes a tool call and returns the result for the model. Errors are
+    /// returned as result text, so the model can correct its call.
+    async fn execute(
+        &self,
+        call: &ToolCall,
+        retrieval: &RetrievalParams,
+        sources: &mut Vec<SearchResult>,
+    ) -> String {
+        let result = match call.name.as_str() {
+            "hybrid_search" => self.search(call, retrieval, sources).await,
+            "fetch_document" => self.fetch_document(call, retrieval, sources).await,
+            "list_documents" => self.list_documents(retrieval).await,
+            name => Err(format!("Unknown tool '{}'", name)),
+        };
+
+        result.unwrap_or_else(|err| {
+            tracing::warn!("Tool call {} failed: {}", call.name, err);
+            format!("Error: {}", err)
+        })
+    }
+
+    async fn search(
+        &self,
+        call: &ToolCall,
+        retrieval: &RetrievalParams,
+        sources: &mut Vec<SearchResult>,
+    ) -> Result<String, String> {
+        let arguments: SearchArguments = serde_json::from_value(call.arguments.clone())
+            .map_err(|err| format!("Invalid arguments: {}", err))?;
+        let params = RetrievalParams {
+            top_k: arguments
+                .top_k
+                .unwrap_or(retrieval.top_k)
+                .clamp(1, MAX_SEARCH_TOP_K),
+            ..retrieval.clone()
+        };
+
+        let results = self
+            .retrieval_service
+            .retrieve(&arguments.query, &params)
+            .await
+            .map_err(|err| err.to_string())?;
+        if results.is_empty() {
+            return Ok("No results.".to_owned());
+        }
+
+        Ok(results
+            .into_iter()
+            .map(|result| add_source(sources, result))
+            .collect())
+    }
+
+    /// Fetches a document within the filter of the turn; ids outside of it
+    /// are reported as unknown.
+    async fn fetch_document(
+        &self,
+        call: &ToolCall,
+        retrieval: &RetrievalParams,
+        sources: &mut Vec<SearchResult>,
+    ) -> Result<String, String> {
+        let arguments: FetchDocumentArguments = serde_json::from_value(call.arguments.clone())
+            .map_err(|err| format!("Invalid arguments: {}", err))?;
+
+        let chunks = self
+            .retrieval_service
+            .meilisearch_service
+            .document_chunks(&arguments.document_id, retrieval.filter.as_deref())
+            .await
+            .map_err(|err| err.to_string())?;
+        if chunks.is_empty() {
+            return Err(format!(
+                "No document with id '{}' in the scope of this chat",
+                arguments.document_id
+            ));
+        }
+        let chunks = self.retrieval_service.screener.screen(chunks);
+        let Some(first) = chunks.first() else {
+            return Err(format!(
+                "Document '{}' was withheld by the prompt injection screening",
+                arguments.document_id
+            ));
+        };
+
+        let mut content = chunks
+            .iter()
+            .map(|chunk| chunk.content.trim())
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        if let Some((cut, _)) = content.char_indices().nth(MAX_DOCUMENT_CHARS) {
+            content.truncate(cut);
+            content.push_str(" [truncated]");
+        }
+
+        // Not the document id, a single-chunk document shares it with its chunk
+        // and would be deduplicated against an earlier search hit
+        let document = SearchResult {
+            id: format!("doc:{}", arguments.document_id),
+            content,
+            chunk_index: None,
+            rerank_score: None,
+            ranking_score: None,
+            ..first.clone()
+        };
+        Ok(add_source(sources, document))
+    }
+
+    async fn list_documents(&self, retrieval: &RetrievalParams) -> Result<String, String> {
+        let mut documents = self
+            .retrieval_service
+            .meilisearch_service
+            .list_documents(retrieval.filter.as_deref())
+            .await
+            .map_err(|err| err.to_string())?;
+        if documents.is_empty() {
+            return Ok(match retrieval.filter {
+                Some(_) => "No documents match the filter of this chat.".to_owned(),
+                None => "The knowledge base is empty.".to_owned(),
+            });
+        }
+
+        documents.truncate(MAX_LISTED_DOCUMENTS);
+        Ok(documents
+            .iter()
+            .map(|document| format!("- {} (document_id: {})\n", document.title, document.id))
+            .collect())
+    }
+}
+
+/// Adds a result to the sources of the turn unless it is already part of
+/// them, and returns it formatted with its source number and document id.
+fn add_source(sources: &mut Vec<SearchResult>, result: SearchResult) -> String {
+    let number = match sources.iter().position(|source| source.id == result.id) {
+        Some(index) => index + 1,
+        None => {
+            sources.push(result);
+            sources.len()
+        }
+    };
+
+    let source = &sources[number - 1];
+    let block = format_source_block(number, source);
+    match (&source.document_id, block.split_once('\n')) {
+        (Some(document_id), Some((header, rest))) => {
+            format!("{}\nDocument ID: {}\n{}", header, document_id, rest)
+        }
+        _ => block,
+    }
+}
+
+fn tool_definitions() -> Vec<ToolDefinition> {
+    vec![
+        ToolDefinition {
+            name: "hybrid_search",
+            description: "Searches the knowledge base with combined keyword and semantic search and returns the best matching passages as numbered sources.",
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "query": {
+                        "type": "string",
+                        "description": "Search query, in the language of the documents"
+                    },
+                    "top_k": {
+                        "type": "integer",
+                        "description": "Number of passages to return",
+                        "minimum": 1,
+                        "maximum": MAX_SEARCH_TOP_K
+                    }
+                },
+                "required": ["query"]
+            }),
+        },
+        ToolDefinition {
+            name: "fetch_document",
+            description: "Returns the full text of a document as a numbered source. Use the document_id from a search result or from list_documents.",
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "document_id": { "type": "string" }
+                },
+                "required": ["document_id"]
+            }),
+        },
+        ToolDefinition {
+            name: "list_documents",
+            description: "Lists the titles and ids of the documents in the knowledge base.",
+            parameters: json!({ "type": "object", "properties": {} }),
+        },
+    ]
+}
@@ -0,0 +1,182 @@
+use crate::models::{Citation, SearchResult};
+use regex::Regex;
+use std::sync::LazyLock;
+
+/// Matches `[Source 1]`, `[Source 1, 3]`, `[Sources 1 and 2]` and the German `[Quelle 1]`,
+/// optionally followed by the title as in the context header, e.g. `[Source 1: Title]`.
+static MARKER_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?i)\[\s*(?:source|quelle)[ns]?\s*(\d+(?:\s*(?:,|;|&|and|und)\s*(?:(?:source|quelle)\s*)?\d+)*)\s*(?::[^\]\n]*)?\]",
+    )
+    .expect("citation marker regex is valid")
+});
+
+static NUMBER_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\d+").expect("number regex is valid"));
+
+/// Parses the citation markers in a generated answer and maps each referenced
+/// source number to the `SearchResult` it was given as in the context.
+///
+/// Markers referring to a source number that was not part of the context are
+/// returned with `valid: false`.
+pub fn extract_citations(answer: &str, sources: &[SearchResult]) -> Vec<Citation> {
+    let mut citations = Vec::new();
+
+    for marker in MARKER_RE.captures_iter(answer) {
+        let whole = marker.get(0).expect("capture 0 is the whole match");
+        let statement = statement_before(answer, whole.start());
+
+        for number in NUMBER_RE.find_iter(&marker[1]) {
+            let Ok(source_number) = number.as_str().parse::<usize>() else {
+                continue;
+            };
+            let source = source_number
+                .checked_sub(1)
+                .and_then(|index| sources.get(index));
+
+            if source.is_none() {
+                tracing::warn!(
+                    "Answer cites [Source {}] but only {} sources were provided",
+                    source_number,
+                    sources.len()
+                );
+            }
+
+            citations.push(Citation {
+                marker: whole.as_str().to_owned(),
+                source_number,
+                statement: statement.clone(),
+                source_id: source.map(|source| source.id.to_owned()),
+                title: source.map(|source| source.title.to_owned()),
+                source_file: source.and_then(|source| source.source_file.to_owned()),
+                valid: source.is_some(),
+            });
+        }
+    }
+
+    citations
+}
+
+/// Returns the sentence a marker at `marker_start` refers to: the text since
+/// the previous sentence boundary, or the previous sentence if the marker
+/// directly follows a full stop.
+fn statement_before(answer: &str, marker_start: usize) -> String {
+    let before = MARKER_RE.replace_all(&answer[..marker_start], "");
+    let body = before
+        .trim_end()
+        .trim_end_matches(['.', '!', '?', ':'])
+        .trim_end();
+
+    let sentence_start = body
+        .rfind(['.', '!', '?', '\n'])
+        .map(|index| index + 1)
+        .unwrap_or(0);
+
+    body[sentence_start..].trim().to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::DocumentMetadata;
+
+    fn sources(count: usize) -> Vec<SearchResult> {
+        (1..=count)
+            .map(|number| SearchResult {
+                id: format!("chunk-{}", number),
+                title: format!("Title {}", number),
+                content: String::new(),
+                metadata: DocumentMetadata::default(),
+                source_file: Some(format!("file-{}.md", number)),
+                document_id: None,
+                chunk_index: None,
+                rerank_score: None,
+                ranking_score: None,
+                merged_chunk_ids: Vec::new(),
+            })
+            .collect()
+    }
+
+    fn cited(citations: &[Citation]) -> Vec<(usize, Option<&str>, bool)> {
+        citations
+            .iter()
+            .map(|citation| {
+                (
+                    citation.source_number,
+                    citation.source_id.as_deref(),
+                    citation.valid,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn maps_a_single_marker_to_its_source() {
+        let citations = extract_citations("Rent is due monthly [Source 1].", &sources(2));
+
+        assert_eq!(cited(&citations), [(1, Some("chunk-1"), true)]);
+        assert_eq!(citations[0].marker, "[Source 1]");
+        assert_eq!(citations[0].statement, "Rent is due monthly");
+        assert_eq!(citations[0].title.as_deref(), Some("Title 1"));
+        assert_eq!(citations[0].source_file.as_deref(), Some("file-1.md"));
+    }
+
+    #[test]
+    fn splits_markers_with_several_sources() {
+        assert_eq!(
+            cited(&extract_citations(
+                "Both agree [Sources 1, 2].",
+                &sources(3)
+            )),
+            [(1, Some("chunk-1"), true), (2, Some("chunk-2"), true)]
+        );
+        assert_eq!(
+            cited(&extract_citations(
+                "Die Frist [Quelle 2 und 3].",
+                &sources(3)
+            )),
+            [(2, Some("chunk-2"), true), (3, Some("chunk-3"), true)]
+        );
+    }
+
+    #[test]
+    fn accepts_the_context_header_format() {
+        let citations = extract_citations("Rent is due monthly [Source 2: Title 2].", &sources(2));
+
+        assert_eq!(cited(&citations), [(2, Some("chunk-2"), true)]);
+        assert_eq!(citations[0].marker, "[Source 2: Title 2]");
+        assert_eq!(citations[0].statement, "Rent is due monthly");
+    }
+
+    #[test]
+    fn flags_sources_outside_the_context() {
+        assert_eq!(
+            cited(&extract_citations("Made up [Source 9].", &sources(2))),
+            [(9, None, false)]
+        );
+        assert_eq!(
+            cited(&extract_citations("Made up [Source 0].", &sources(2))),
+            [(0, None, false)]
+        );
+    }
+
+    #[test]
+    fn keeps_every_occurrence_of_duplicate_markers() {
+        let citations = extract_citations(
+            "Rent is due monthly [Source 1]. It is paid by transfer [Source 1].",
+            &sources(1),
+        );
+
+        assert_eq!(
+            cited(&citations),
+            [(1, Some("chunk-1"), true), (1, Some("chunk-1"), true)]
+        );
+        assert_eq!(citations[0].statement, "Rent is due monthly");
+        assert_eq!(citations[1].statement, "It is paid by transfer");
+    }
+
+    #[test]
+    fn ignores_text_without_markers() {
+        assert!(extract_citations("No sources [here] or [Source].", &sources(1)).is_empty());
+    }
+}
@@ -0,0 +1,349 @@
+use crate::models::{ChatMessage, SearchResult};
+use std::env;
+use tiktoken_rs::CoreBPE;
+
+/// Longest overlap (in bytes) looked for when stitching adjacent chunks together.
+const MAX_CHUNK_OVERLAP: usize = 2048;
+
+/// Shorter common prefixes/suffixes are treated as coincidence, not chunk overlap.
+const MIN_CHUNK_OVERLAP: usize = 16;
+
+/// Assembles the prompt context from retrieved chunks and the session history
+/// within a token budget.
+pub struct ContextBuilder {
+    bpe: CoreBPE,
+    token_budget: usize,
+    history_share: f32,
+}
+
+/// Result of [`ContextBuilder::build`].
+pub struct BuiltContext {
+    /// Formatted source blocks, numbered `[Source 1]`, `[Source 2]`, ...
+    pub context: String,
+    /// History messages that fit into the history budget, oldest first
+    pub history: Vec<ChatMessage>,
+    /// Sources included in `context`, in citation order, with the chunks merged
+    /// into each in `merged_chunk_ids`
+    pub sources: Vec<SearchResult>,
+}
+
+/// A source block in the making: one hit, or several adjacent chunks of a document.
+struct Passage {
+    result: SearchResult,
+    first_chunk: Option<usize>,
+    last_chunk: Option<usize>,
+    /// Ids of the chunks merged into or contained in `result`, in rank order
+    merged_ids: Vec<String>,
+}
+
+impl ContextBuilder {
+    pub fn new() -> Self {
+        let token_budget = env::var("CONTEXT_TOKEN_BUDGET")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(3072);
+        let history_share = env::var("CONTEXT_HISTORY_SHARE")
+            .ok()
+            .and_then(|value| value.parse::<f32>().ok())
+            .unwrap_or(0.3)
+            .clamp(0.0, 1.0);
+
+        Self {
+            // The served models ship their own tokenizers; cl100k is close enough for budgeting.
+            bpe: tiktoken_rs::cl100k_base().expect("cl100k_base tokenizer is bundled"),
+            token_budget,
+            history_share,
+        }
+    }
+
+    pub fn count_tokens(&self, text: &str) -> usize {
+        self.bpe.encode_ordinary(text).len()
+    }
+
+    /// Fits the most recent history into its share of the budget, then fills the
+    /// remaining budget with deduplicated, merged source passages in rank order.
+    pub fn build(&self, history: &[ChatMessage], results: Vec<SearchResult>) -> BuiltContext {
+        let history_budget = (self.token_budget as f32 * self.history_share) as usize;
+
+        let mut history_tokens = 0;
+        let mut kept_history = Vec::new();
+        for message in history.iter().rev() {
+            let tokens = self.count_tokens(&message.content);
+            if history_tokens + tokens > history_budget {
+                break;
+            }
+            history_tokens += tokens;
+            kept_history.push(message.clone());
+        }
+        kept_history.reverse();
+
+        let source_budget = self.token_budget.saturating_sub(history_tokens);
+        let passages = merge_passages(results);
+
+        let mut source_tokens = 0;
+        let mut context = String::new();
+        let mut sources = Vec::new();
+        for passage in passages {
+            let block = format_source_block(sources.len() + 1, &passage.result);
+            let tokens = self.count_tokens(&block);
+            if source_tokens + tokens > source_budget {
+                tracing::debug!(
+                    "Skipping source '{}' ({} tokens), context budget exhausted",
+                    passage.result.id,
+                    tokens
+                );
+                continue;
+            }
+            source_tokens += tokens;
+            context.push_str(&block);
+            sources.push(SearchResult {
+                merged_chunk_ids: passage.merged_ids,
+                ..passage.result
+            });
+        }
+
+        tracing::info!(
+            "Built context with {} sources ({} tokens) and {} history messages ({} tokens)",
+            sources.len(),
+            source_tokens,
+            kept_history.len(),
+            history_tokens
+        );
+
+        BuiltContext {
+            context,
+            history: kept_history,
+            sources,
+        }
+    }
+}
+
+/// Formats a result as `[Source n: title]` block, the form cited in answers.
+pub fn format_source_block(number: usize, result: &SearchResult) -> String {
+    let mut block = format!("[Source {}: {}]\n", number, result.title);
+
+    if let Some(source_file) = &result.source_file {
+        block.push_str(&format!("File: {}\n", source_file));
+    }
+
+    block.push_str(&format!("Content: {}\n", result.content));
+    block.push_str("\n---\n\n");
+
+    block
+}
+
+fn document_key(result: &SearchResult) -> Option<&str> {
+    result
+        .document_id
+        .as_deref()
+        .or(result.source_file.as_deref())
+}
+
+/// Drops empty and duplicate chunks, and chunks contained in another chunk of
+/// the same document, and merges chunks that are adjacent in the same document,
+/// keeping the rank of the best hit of each passage.
+fn merge_passages(results: Vec<SearchResult>) -> Vec<Passage> {
+    let mut passages: Vec<Passage> = Vec::new();
+
+    'results: for result in results {
+        let content = result.content.trim();
+        // Would be "contained" in any passage, and adds nothing on its own
+        if content.is_empty() {
+            continue;
+        }
+
+        for passage in passages.iter_mut() {
+            if passage.result.id == result.id {
+                continue 'results;
+            }
+
+            let same_document = document_key(&passage.result).is_some()
+                && document_key(&passage.result) == document_key(&result);
+            if !same_document {
+                continue;
+            }
+
+            if passage.result.content.contains(content) {
+                passage.merged_ids.push(result.id.clone());
+                continue 'results;
+            }
+
+            if let (Some(first), Some(last), Some(index)) =
+                (passage.first_chunk, passage.last_chunk, result.chunk_index)
+            {
+                if index == last + 1 {
+                    passage.result.content = join_overlapping(&passage.result.content, content);
+                    passage.last_chunk = Some(index);
+                    passage.merged_ids.push(result.id.clone());
+                    continue 'results;
+                }
+                if index + 1 == first {
+                    passage.result.content = join_overlapping(content, &passage.result.content);
+                    passage.first_chunk = Some(index);
+                    passage.merged_ids.push(result.id.clone());
+                    continue 'results;
+                }
+            }
+        }
+
+        passages.push(Passage {
+            first_chunk: result.chunk_index,
+            last_chunk: result.chunk_index,
+            merged_ids: Vec::new(),
+            result,
+        });
+    }
+
+    passages
+}
+
+/// Concatenates two consecutive chunks, removing the text they share because of
+/// the chunker's overlap.
+pub fn join_overlapping(first: &str, second: &str) -> String {
+    let max = first.len().min(second.len()).min(MAX_CHUNK_OVERLAP);
+
+    for len in (MIN_CHUNK_OVERLAP..=max).rev() {
+        if second.is_char_boundary(len) && first.ends_with(&second[..len]) {
+            return format!("{}{}", first, &second[len..]);
+        }
+    }
+
+    format!("{}\n{}", first, second)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::DocumentMetadata;
+
+    fn chunk(id: &str, document_id: &str, chunk_index: usize, content: &str) -> SearchResult {
+        SearchResult {
+            id: id.to_owned(),
+            title: document_id.to_owned(),
+            content: content.to_owned(),
+            metadata: DocumentMetadata::default(),
+            source_file: None,
+            document_id: Some(document_id.to_owned()),
+            chunk_index: Some(chunk_index),
+            rerank_score: None,
+            ranking_score: None,
+            merged_chunk_ids: Vec::new(),
+        }
+    }
+
+    /// ASCII text without repeating substrings, so overlaps are unambiguous.
+    fn text(from: usize, len: usize) -> String {
+        (from..)
+            .flat_map(|number| format!("{} ", number).into_bytes())
+            .take(len)
+            .map(char::from)
+            .collect()
+    }
+
+    fn texts_with_overlap(overlap: usize) -> (String, String) {
+        let shared = text(1000, overlap);
+        (
+            format!("{}{}", text(0, 100).replace(' ', "-"), shared),
+            format!("{}{}", shared, text(5000, 100).replace(' ', "_")),
+        )
+    }
+
+    #[test]
+    fn joins_overlaps_at_the_bounds() {
+        for overlap in [MIN_CHUNK_OVERLAP, MAX_CHUNK_OVERLAP] {
+            let (first, second) = texts_with_overlap(overlap);
+
+            assert_eq!(
+                join_overlapping(&first, &second),
+                format!("{}{}", first, &second[overlap..]),
+                "overlap {}",
+                overlap
+            );
+        }
+    }
+
+    #[test]
+    fn keeps_overlaps_outside_the_bounds() {
+        for overlap in [MIN_CHUNK_OVERLAP - 1, MAX_CHUNK_OVERLAP + 1] {
+            let (first, second) = texts_with_overlap(overlap);
+
+            assert_eq!(
+                join_overlapping(&first, &second),
+                format!("{}\n{}", first, second),
+                "overlap {}",
+                overlap
+            );
+        }
+    }
+
+    #[test]
+    fn merges_adjacent_chunks_of_a_document() {
+        let passages = merge_passages(vec![
+            chunk("doc-1", "doc", 1, "Second chunk."),
+            chunk("doc-2", "doc", 2, "Third chunk."),
+            chunk("doc-0", "doc", 0, "First chunk."),
+            chunk("doc-5", "doc", 5, "Sixth chunk."),
+            chunk("other-3", "other", 3, "Other document."),
+        ]);
+
+        assert_eq!(passages.len(), 3);
+        assert_eq!(passages[0].result.id, "doc-1");
+        assert_eq!(
+            passages[0].result.content,
+            "First chunk.\nSecond chunk.\nThird chunk."
+        );
+        assert_eq!(
+            (passages[0].first_chunk, passages[0].last_chunk),
+            (Some(0), Some(2))
+        );
+        assert_eq!(passages[0].merged_ids, ["doc-2", "doc-0"]);
+        assert_eq!(passages[1].result.id, "doc-5");
+        assert_eq!(passages[2].result.id, "other-3");
+    }
+
+    #[test]
+    fn drops_empty_duplicate_and_contained_chunks() {
+        let passages = merge_passages(vec![
+            chunk("empty", "doc", 7, "  \n "),
+            chunk("doc-0", "doc", 0, "A long chunk about hybrid search."),
+            chunk("doc-0", "doc", 0, "A long chunk about hybrid search."),
+            chunk("doc-4", "doc", 4, "hybrid search"),
+            chunk("also-empty", "doc", 1, ""),
+        ]);
+
+        assert_eq!(passages.len(), 1);
+        assert_eq!(passages[0].result.id, "doc-0");
+        assert_eq!(passages[0].last_chunk, Some(0));
+        assert_eq!(passages[0].merged_ids, ["doc-4"]);
+    }
+
+    #[test]
+    fn keeps_chunks_contained_in_other_documents() {
+        let passages = merge_passages(vec![
+            chunk("doc-0", "doc", 0, "A long chunk about hybrid search."),
+            chunk("copy-3", "copy", 3, "hybrid search"),
+        ]);
+
+        assert_eq!(passages.len(), 2);
+        assert_eq!(passages[1].result.id, "copy-3");
+        assert!(passages.iter().all(|passage| passage.merged_ids.is_empty()));
+    }
+
+    #[test]
+    fn reports_merged_chunks_with_their_source() {
+        let builder = ContextBuilder::new();
+        let built = builder.build(
+            &[],
+            vec![
+                chunk("doc-1", "doc", 1, "Second chunk."),
+                chunk("doc-0", "doc", 0, "First chunk."),
+                chunk("other-3", "other", 3, "Other document."),
+            ],
+        );
+
+        assert_eq!(built.sources.len(), 2);
+        assert_eq!(built.sources[0].id, "doc-1");
+        assert_eq!(built.sources[0].merged_chunk_ids, ["doc-0"]);
+        assert!(built.sources[1].merged_chunk_ids.is_empty());
+    }
+}
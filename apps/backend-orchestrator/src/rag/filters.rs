@@ -0,0 +1,388 @@
+use crate::error::{RagError, Result};
+use crate::models::{RetrievalOptions, StructuredFilters};
+use regex::Regex;
+use std::sync::LazyLock;
+
+/// Attribute names in a Meilisearch filter expression: an identifier followed by
+/// a comparison, `IN`, `EXISTS`, `IS`, `CONTAINS`, `STARTS WITH` or a
+/// `low TO high` range, each but the range optionally negated with `NOT`.
+static ATTRIBUTE_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?i)([A-Za-z_][A-Za-z0-9_.]*)\s*(?:!=|>=|<=|=|>|<|\s(?:NOT\s+)?IN\s*\[|\s(?:NOT\s+)?EXISTS\b|\sIS\s|\s(?:NOT\s+)?CONTAINS\s|\s(?:NOT\s+)?STARTS\s+WITH\s|\s+[^\s()]+\s+TO\s)",
+    )
+    .expect("filter attribute regex is valid")
+});
+
+static QUOTED_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#""(?:[^"\\]|\\.)*"|'(?:[^'\\]|\\.)*'"#).expect("quoted string regex is valid")
+});
+
+/// Builds the Meilisearch filter for a request from the raw `filter` expression,
+/// the structured filters and the document scope, combined with `AND`.
+///
+/// Every attribute used must be one of `filterable`.
+pub fn build_filter(options: &RetrievalOptions, filterable: &[&str]) -> Result<Option<String>> {
+    let mut generated = Vec::new();
+
+    if let Some(filters) = &options.filters {
+        generated.extend(structured_clauses(filters)?);
+    }
+
+    if let Some(document_ids) = &options.document_ids
+        && !document_ids.is_empty()
+    {
+        generated.push(format!("document_id IN [{}]", quote_all(document_ids)));
+    }
+
+    let mut clauses = Vec::new();
+    if let Some(expression) = options.filter.as_deref().map(str::trim)
+        && !expression.is_empty()
+    {
+        check_parentheses(expression)?;
+        clauses.push(format!("({})", expression));
+    }
+    clauses.extend(generated);
+
+    for clause in &clauses {
+        validate_expression(clause, filterable)?;
+    }
+
+    Ok((!clauses.is_empty()).then(|| clauses.join(" AND ")))
+}
+
+fn structured_clauses(filters: &StructuredFilters) -> Result<Vec<String>> {
+    let mut clauses = Vec::new();
+
+    if let Some(tags) = &filters.tags
+        && !tags.is_empty()
+    {
+        clauses.push(format!("metadata.tags IN [{}]", quote_all(tags)));
+    }
+    if let Some(author) = &filters.author {
+        clauses.push(format!("metadata.author = {}", quote(author)));
+    }
+    if let Some(document_type) = &filters.document_type {
+        clauses.push(format!("metadata.document_type = {}", quote(document_type)));
+    }
+    if let Some(language) = filters.language {
+        clauses.push(format!("metadata.language = {}", quote(language.code())));
+    }
+    if let Some(after) = &filters.created_after {
+        clauses.push(format!(
+            "created_at_timestamp >= {}",
+            parse_timestamp(after)?
+        ));
+    }
+    if let Some(before) = &filters.created_before {
+        // A plain date includes the whole day
+        clauses.push(match parse_date(before).and_then(|date| date.succ_opt()) {
+            Some(next_day) => format!("created_at_timestamp < {}", start_of_day(next_day)),
+            None => format!("created_at_timestamp <= {}", parse_timestamp(before)?),
+        });
+    }
+
+    Ok(clauses)
+}
+
+/// Checks that every attribute referenced by `expression` is filterable.
+fn validate_expression(expression: &str, filterable: &[&str]) -> Result<()> {
+    let unquoted = QUOTED_RE.replace_all(expression, "\"\"");
+
+    for attribute in ATTRIBUTE_RE.captures_iter(&unquoted) {
+        let name = &attribute[1];
+        if matches!(name.to_ascii_uppercase().as_str(), "AND" | "OR" | "NOT") {
+            continue;
+        }
+        if !filterable.contains(&name) {
+            return Err(RagError::InvalidRequest(format!(
+                "Attribute '{}' is not filterable. Filterable attributes: {}",
+                name,
+                filterable.join(", ")
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// Rejects unbalanced parentheses outside of quoted values, which would let
+/// the raw filter close its own group and escape the `AND` with the others.
+fn check_parentheses(expression: &str) -> Result<()> {
+    let unquoted = QUOTED_RE.replace_all(expression, "\"\"");
+    let mut depth = 0usize;
+
+    for character in unquoted.chars() {
+        match character {
+            '(' => depth += 1,
+            ')' => {
+                depth = depth.checked_sub(1).ok_or_else(unbalanced)?;
+            }
+            _ => {}
+        }
+    }
+
+    if depth == 0 {
+        Ok(())
+    } else {
+        Err(unbalanced())
+    }
+}
+
+fn unbalanced() -> RagError {
+    RagError::InvalidRequest("Unbalanced parentheses in filter".to_owned())
+}
+
+/// Accepts RFC 3339 timestamps and plain `YYYY-MM-DD` dates, the latter as
+/// the start of the day in UTC.
+fn parse_timestamp(value: &str) -> Result<i64> {
+    if let Ok(datetime) = chrono::DateTime::parse_from_rfc3339(value) {
+        return Ok(datetime.timestamp());
+    }
+
+    parse_date(value).map(start_of_day).ok_or_else(|| {
+        RagError::InvalidRequest(format!(
+            "Invalid date '{}', expected RFC 3339 or YYYY-MM-DD",
+            value
+        ))
+    })
+}
+
+fn parse_date(value: &str) -> Option<chrono::NaiveDate> {
+    chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d").ok()
+}
+
+fn start_of_day(date: chrono::NaiveDate) -> i64 {
+    date.and_time(chrono::NaiveTime::MIN).and_utc().timestamp()
+}
+
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn quote_all(values: &[String]) -> String {
+    values
+        .iter()
+        .map(|value| quote(value))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Validates `top_k`, `semantic_ratio` and `neighbor_chunks` of a request.
+pub fn validate_options(
+    options: &RetrievalOptions,
+    max_top_k: usize,
+    max_neighbor_chunks: usize,
+) -> Result<()> {
+    if let Some(top_k) = options.top_k
+        && !(1..=max_top_k).contains(&top_k)
+    {
+        return Err(RagError::InvalidRequest(format!(
+            "top_k must be between 1 and {}",
+            max_top_k
+        )));
+    }
+
+    if let Some(ratio) = options.semantic_ratio
+        && !(0.0..=1.0).contains(&ratio)
+    {
+        return Err(RagError::InvalidRequest(
+            "semantic_ratio must be between 0.0 and 1.0".to_string(),
+        ));
+    }
+
+    if let Some(neighbor_chunks) = options.neighbor_chunks
+        && neighbor_chunks > max_neighbor_chunks
+    {
+        return Err(RagError::InvalidRequest(format!(
+            "neighbor_chunks must be at most {}",
+            max_neighbor_chunks
+        )));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::meilisearch::FILTERABLE_ATTRIBUTES;
+
+    fn filter(options: RetrievalOptions) -> Result<Option<String>> {
+        build_filter(&options, FILTERABLE_ATTRIBUTES)
+    }
+
+    fn structured(filters: StructuredFilters) -> Result<Option<String>> {
+        filter(RetrievalOptions {
+            filters: Some(filters),
+            ..RetrievalOptions::default()
+        })
+    }
+
+    #[test]
+    fn escapes_quotes_and_backslashes() {
+        let built = structured(StructuredFilters {
+            author: Some(r#"O"Brien \ Co"#.to_owned()),
+            tags: Some(vec![r#"a"b"#.to_owned(), r"c\".to_owned()]),
+            ..StructuredFilters::default()
+        })
+        .unwrap();
+
+        assert_eq!(
+            built.as_deref(),
+            Some(r#"metadata.tags IN ["a\"b", "c\\"] AND metadata.author = "O\"Brien \\ Co""#)
+        );
+    }
+
+    #[test]
+    fn scopes_to_document_ids() {
+        let built = filter(RetrievalOptions {
+            filter: Some(" chunk_index < 3 ".to_owned()),
+            document_ids: Some(vec!["handbook".to_owned(), "faq \"v2\"".to_owned()]),
+            ..RetrievalOptions::default()
+        })
+        .unwrap();
+
+        assert_eq!(
+            built.as_deref(),
+            Some(r#"(chunk_index < 3) AND document_id IN ["handbook", "faq \"v2\""]"#)
+        );
+        assert_eq!(
+            filter(RetrievalOptions {
+                document_ids: Some(Vec::new()),
+                ..RetrievalOptions::default()
+            })
+            .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn rejects_attributes_that_are_not_filterable() {
+        let error = filter(RetrievalOptions {
+            filter: Some("metadata.author = \"x\" AND content = \"secret\"".to_owned()),
+            ..RetrievalOptions::default()
+        })
+        .unwrap_err();
+
+        assert!(
+            matches!(&error, RagError::InvalidRequest(message) if message.starts_with("Attribute 'content' is not filterable")),
+            "{:?}",
+            error
+        );
+    }
+
+    #[test]
+    fn ignores_attribute_names_inside_quoted_values() {
+        assert!(
+            validate_expression(
+                r#"metadata.author = "content = x" OR source_file IN ['a\' IN [b']"#,
+                FILTERABLE_ATTRIBUTES
+            )
+            .is_ok()
+        );
+        assert!(validate_expression("chunk_index 1 TO 3", FILTERABLE_ATTRIBUTES).is_ok());
+        assert!(validate_expression("content EXISTS", FILTERABLE_ATTRIBUTES).is_err());
+    }
+
+    #[test]
+    fn checks_attributes_of_contains_starts_with_and_negations() {
+        for expression in [
+            r#"content CONTAINS "secret""#,
+            r#"content NOT CONTAINS "secret""#,
+            r#"content STARTS WITH "secret""#,
+            r#"content NOT STARTS WITH "secret""#,
+            r#"NOT content = "secret""#,
+            r#"NOT content IN ["secret"]"#,
+            "NOT content EXISTS",
+        ] {
+            assert!(
+                validate_expression(expression, FILTERABLE_ATTRIBUTES).is_err(),
+                "{}",
+                expression
+            );
+        }
+        assert!(
+            validate_expression(
+                r#"NOT metadata.author STARTS WITH "O'" AND source_file NOT CONTAINS "tmp""#,
+                FILTERABLE_ATTRIBUTES
+            )
+            .is_ok()
+        );
+    }
+
+    #[test]
+    fn rejects_unbalanced_parentheses_in_the_raw_filter() {
+        let scoped = |expression: &str| {
+            filter(RetrievalOptions {
+                filter: Some(expression.to_owned()),
+                document_ids: Some(vec!["handbook".to_owned()]),
+                ..RetrievalOptions::default()
+            })
+        };
+
+        for expression in [
+            "chunk_index = 1) OR (chunk_index = 2",
+            "(chunk_index = 1",
+            "chunk_index = 1)",
+        ] {
+            assert!(
+                matches!(scoped(expression), Err(RagError::InvalidRequest(_))),
+                "{}",
+                expression
+            );
+        }
+        assert_eq!(
+            scoped(r#"(chunk_index = 1 OR metadata.author = ")(")"#)
+                .unwrap()
+                .as_deref(),
+            Some(
+                r#"((chunk_index = 1 OR metadata.author = ")(")) AND document_id IN ["handbook"]"#
+            )
+        );
+    }
+
+    #[test]
+    fn converts_dates_to_a_timestamp_range() {
+        let built = structured(StructuredFilters {
+            created_after: Some("2024-03-01".to_owned()),
+            created_before: Some("2024-03-01T12:00:00+01:00".to_owned()),
+            ..StructuredFilters::default()
+        })
+        .unwrap();
+
+        assert_eq!(
+            built.as_deref(),
+            Some("created_at_timestamp >= 1709251200 AND created_at_timestamp <= 1709290800")
+        );
+        assert!(matches!(
+            structured(StructuredFilters {
+                created_before: Some("2024-03-32".to_owned()),
+                ..StructuredFilters::default()
+            }),
+            Err(RagError::InvalidRequest(_))
+        ));
+        assert!(matches!(
+            structured(StructuredFilters {
+                created_after: Some("01.03.2024".to_owned()),
+                ..StructuredFilters::default()
+            }),
+            Err(RagError::InvalidRequest(_))
+        ));
+    }
+
+    #[test]
+    fn includes_the_whole_day_of_a_date_only_upper_bound() {
+        let built = structured(StructuredFilters {
+            created_after: Some("2024-03-01".to_owned()),
+            created_before: Some("2024-03-01".to_owned()),
+            ..StructuredFilters::default()
+        })
+        .unwrap();
+
+        // 2024-03-01T00:00:00Z <= created_at < 2024-03-02T00:00:00Z
+        assert_eq!(
+            built.as_deref(),
+            Some("created_at_timestamp >= 1709251200 AND created_at_timestamp < 1709337600")
+        );
+    }
+}
@@ -0,0 +1,196 @@
+use crate::models::{Language, SearchResult};
+use std::collections::HashSet;
+
+pub const FOLLOW_UP_SYSTEM_PROMPT: &str = r#"You suggest follow-up questions for users of a knowledge base chat.
+
+You are given a question, the answer it received and excerpts of documents the answer did not fully use. Write questions the user could ask next that these excerpts answer. Each question must be answerable from the excerpts alone, short and understandable without the conversation.
+
+Reply with one question per line, without numbering, explanations or quotes."#;
+
+/// Number of follow-up questions requested; fewer than the minimum are not sent.
+pub const MIN_QUESTIONS: usize = 2;
+pub const MAX_QUESTIONS: usize = 4;
+
+/// A source counts as fully covered when the answer contains at least this
+/// share of its distinct words.
+const COVERED_SHARE: f64 = 0.6;
+
+/// Number of uncovered sources passed to the LLM, least covered first.
+const MAX_SOURCES: usize = 3;
+
+/// Source excerpts in the prompt are cut off after this many characters.
+const MAX_SOURCE_CHARS: usize = 1500;
+
+/// Words shorter than this are ignored for coverage, mostly stop words.
+const MIN_WORD_CHARS: usize = 5;
+
+/// Returns the sources the answer did not fully cover, least covered first.
+pub fn uncovered_sources<'a>(answer: &str, sources: &'a [SearchResult]) -> Vec<&'a SearchResult> {
+    let answer_words = words(answer);
+
+    let mut uncovered: Vec<(f64, &SearchResult)> = sources
+        .iter()
+        .filter_map(|source| {
+            let source_words = words(&source.content);
+            if source_words.is_empty() {
+                return None;
+            }
+            let covered =
+                source_words.intersection(&answer_words).count() as f64 / source_words.len() as f64;
+            (covered < COVERED_SHARE).then_some((covered, source))
+        })
+        .collect();
+    uncovered.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+
+    uncovered
+        .into_iter()
+        .take(MAX_SOURCES)
+        .map(|(_, source)| source)
+        .collect()
+}
+
+pub fn build_prompt(
+    question: &str,
+    answer: &str,
+    sources: &[&SearchResult],
+    language: Language,
+) -> String {
+    let excerpts: Vec<String> = sources
+        .iter()
+        .map(|source| {
+            let content: String = source.content.chars().take(MAX_SOURCE_CHARS).collect();
+            format!("[{}]\n{}", source.title, content)
+        })
+        .collect();
+
+    format!(
+        "Question: {}\n\nAnswer: {}\n\nExcerpts:\n\n{}\n\nWrite {} to {} follow-up questions in {}.",
+        question,
+        answer,
+        excerpts.join("\n\n"),
+        MIN_QUESTIONS,
+        MAX_QUESTIONS,
+        language.name()
+    )
+}
+
+/// Parses one question per line, dropping numbering, bullets and duplicates.
+pub fn parse_questions(response: &str) -> Vec<String> {
+    let mut questions: Vec<String> = Vec::new();
+
+    for line in response.lines() {
+        let question = line
+            .trim()
+            .trim_start_matches(|c: char| c.is_ascii_digit() || matches!(c, '.' | ')' | '-' | '*'))
+            .trim()
+            .trim_matches('"')
+            .trim();
+        if question.is_empty() || questions.iter().any(|existing| existing == question) {
+            continue;
+        }
+        questions.push(question.to_owned());
+    }
+    questions.truncate(MAX_QUESTIONS);
+
+    questions
+}
+
+fn words(text: &str) -> HashSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| word.chars().count() >= MIN_WORD_CHARS)
+        .map(str::to_lowercase)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::DocumentMetadata;
+
+    fn source(id: &str, content: &str) -> SearchResult {
+        SearchResult {
+            id: id.to_owned(),
+            title: id.to_owned(),
+            content: content.to_owned(),
+            metadata: DocumentMetadata::default(),
+            source_file: None,
+            document_id: None,
+            chunk_index: None,
+            rerank_score: None,
+            ranking_score: None,
+            merged_chunk_ids: Vec::new(),
+        }
+    }
+
+    fn ids(sources: Vec<&SearchResult>) -> Vec<&str> {
+        sources.iter().map(|source| source.id.as_str()).collect()
+    }
+
+    #[test]
+    fn skips_sources_the_answer_covers() {
+        let sources = [
+            source(
+                "covered",
+                "Meilisearch combines keyword search and vector search.",
+            ),
+            source(
+                "uncovered",
+                "Rerankers reorder candidates with a cross-encoder model.",
+            ),
+        ];
+
+        let uncovered = uncovered_sources(
+            "Meilisearch combines keyword search with vector search.",
+            &sources,
+        );
+
+        assert_eq!(ids(uncovered), ["uncovered"]);
+    }
+
+    #[test]
+    fn orders_least_covered_first_and_limits_the_count() {
+        let sources = [
+            source("half", "alpha12 bravo12 charlie12 delta12"),
+            source("none", "golf1234 hotel1234 india1234 juliet1234"),
+            source("quarter", "alpha12 kilo1234 lima1234 mike1234"),
+            source("also-none", "november1 oscar1234 papa1234 quebec1234"),
+            source("short-words", "a an the of"),
+        ];
+
+        let uncovered = uncovered_sources("alpha12 bravo12", &sources);
+
+        // "short-words" has no word long enough to measure and is never suggested
+        assert_eq!(ids(uncovered), ["none", "also-none", "quarter"]);
+    }
+
+    #[test]
+    fn parses_numbered_and_bulleted_questions() {
+        let response = "1. What is hybrid search?\n\
+                        2) How does reranking work?\n\
+                        \n\
+                        - \"Which model creates the embeddings?\"\n\
+                        * What is hybrid search?\n";
+
+        assert_eq!(
+            parse_questions(response),
+            [
+                "What is hybrid search?",
+                "How does reranking work?",
+                "Which model creates the embeddings?",
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_at_most_the_maximum_number_of_questions() {
+        let response = (1..=6)
+            .map(|number| format!("Question {}?", number))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let questions = parse_questions(&response);
+
+        assert_eq!(questions.len(), MAX_QUESTIONS);
+        assert_eq!(questions[0], "Question 1?");
+    }
+}
@@ -1,42 +1,94 @@
-use crate::models::{ChatResponse, SearchResult};
+use crate::error::Result;
+use crate::models::{ChatResponse, GenerationOptions, GenerationParams, SearchResult};
+use crate::rag::{PromptTemplates, PromptVariables, RenderedPrompt};
+use crate::services::LlmService;
+use crate::services::llm::{AssistantTurn, LlmMessage, StreamToken, TokenStream, ToolDefinition};
+use std::sync::Arc;
 
 pub struct GenerationService {
-    llm_service: LlmService,
+    llm_service: Arc<LlmService>,
+    prompt_templates: Arc<PromptTemplates>,
 }
 
 impl GenerationService {
-    pub fn new() -> Result<Self> {
-        let llm_service = LlmService::new()?;
-        Ok(Self { llm_service })
+    pub fn new(llm_service: Arc<LlmService>, prompt_templates: Arc<PromptTemplates>) -> Self {
+        Self {
+            llm_service,
+            prompt_templates,
+        }
     }
 
-    pub async fn generate_response_stream(
+    /// Renders the prompt template for a turn, see [`PromptTemplates::render`].
+    pub fn render_prompt(
         &self,
-        user_message: &str,
-        context: &str,
-        sources: Vec<SearchResult>,
-    ) -> Result<impl futures::Stream<Item = Result<String>>> {
-        let system_prompt = self.build_system_prompt();
+        template: Option<&str>,
+        variables: &PromptVariables,
+    ) -> Result<RenderedPrompt> {
+        self.prompt_templates.render(template, variables)
+    }
 
+    /// Validates per-request generation overrides, see [`LlmService::resolve_params`].
+    pub fn resolve_params(&self, options: &GenerationOptions) -> Result<GenerationParams> {
+        self.llm_service.resolve_params(options)
+    }
+
+    pub async fn generate_response_stream(
+        &self,
+        prompt: &RenderedPrompt,
+        params: &GenerationParams,
+    ) -> Result<impl futures::Stream<Item = Result<StreamToken>> + use<>> {
         let token_stream = self
             .llm_service
-            .generate_response_stream(&system_prompt, user_message, context)
+            .generate_response_stream(&prompt.system, &prompt.history, &prompt.user, params)
             .await?;
 
         Ok(token_stream)
     }
 
+    /// One step of a tool loop, see [`LlmService::chat_with_tools`].
+    pub async fn chat_with_tools(
+        &self,
+        messages: Vec<LlmMessage>,
+        tools: &[ToolDefinition],
+        params: &GenerationParams,
+    ) -> Result<AssistantTurn> {
+        self.llm_service
+            .chat_with_tools(messages, tools, params)
+            .await
+    }
+
+    /// Streams the final answer of a tool loop.
+    pub async fn stream_messages(
+        &self,
+        messages: Vec<LlmMessage>,
+        params: &GenerationParams,
+    ) -> Result<TokenStream> {
+        self.llm_service.stream_messages(messages, params).await
+    }
+
+    /// Single completion for auxiliary tasks, see [`LlmService::complete`].
+    pub async fn complete(
+        &self,
+        system_prompt: &str,
+        prompt: &str,
+        temperature: f32,
+        max_tokens: u32,
+    ) -> Result<String> {
+        self.llm_service
+            .complete(system_prompt, prompt, temperature, max_tokens)
+            .await
+    }
+
+    #[allow(dead_code)] // non-streaming counterpart, not exposed over HTTP yet
     pub async fn generate_response(
         &self,
-        user_message: &str,
-        context: &str,
+        prompt: &RenderedPrompt,
+        params: &GenerationParams,
         sources: Vec<SearchResult>,
     ) -> Result<ChatResponse> {
-        let system_prompt = self.build_system_prompt();
-
         let response = self
             .llm_service
-            .generate_response(&system_prompt, user_message, context)
+            .generate_response(&prompt.system, &prompt.history, &prompt.user, params)
             .await?;
 
         Ok(ChatResponse {
@@ -45,18 +97,4 @@ impl GenerationService {
             session_id: None,
         })
     }
-
-    fn build_system_prompt(&self) -> String {
-        r#"You are a helpful AI assistant that answers questions based on the provided context from a knowledge base.
-
-Instructions:
-1. Use only the information provided in the context to answer questions
-2. If the context doesn't contain enough information to answer the question, say so clearly
-3. Cite your sources by referencing the source numbers in brackets (e.g., [Source 1], [Source 2])
-4. Be concise but comprehensive in your answers
-5. If asked about something not in the context, politely explain that you don't have that information
-6. Maintain a helpful and professional tone
-
-The context provided will include source information and relevance scores to help you understand the quality of the information."#.to_string()
-    }
 }
@@ -0,0 +1,76 @@
+use crate::models::Language;
+
+/// Frequent German function words.
+const GERMAN_MARKERS: &[&str] = &[
+    "der", "die", "das", "und", "ist", "nicht", "wie", "wer", "wieso", "wo", "warum", "welche",
+    "ich", "ein", "eine", "mit", "für", "gibt", "kann", "können", "auf", "sich", "dem", "den",
+    "des", "zu", "im", "von", "sind", "wird", "werden", "oder", "auch", "bei", "nach",
+];
+
+/// Frequent English function words.
+const ENGLISH_MARKERS: &[&str] = &[
+    "the", "and", "is", "are", "not", "how", "who", "why", "where", "which", "what", "a", "an",
+    "with", "for", "can", "does", "do", "of", "to", "in", "on", "it", "this", "that", "be", "by",
+    "or", "from", "should",
+];
+
+/// Detects whether `text` is German or English by counting function words,
+/// with umlauts and `ß` as a tie-breaker. Defaults to English.
+pub fn detect(text: &str) -> Language {
+    let lowercase = text.to_lowercase();
+    let (mut german, mut english) = (0usize, 0usize);
+
+    for word in lowercase.split(|c: char| !c.is_alphanumeric()) {
+        if GERMAN_MARKERS.contains(&word) {
+            german += 1;
+        }
+        if ENGLISH_MARKERS.contains(&word) {
+            english += 1;
+        }
+    }
+    if lowercase.contains(['ä', 'ö', 'ü', 'ß']) {
+        german += 1;
+    }
+
+    if german > english {
+        Language::De
+    } else {
+        Language::En
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_german() {
+        assert_eq!(detect("Wie hoch ist die Miete?"), Language::De);
+        assert_eq!(detect("Die Kündigungsfrist"), Language::De);
+        assert_eq!(detect("Die Antwort bitte"), Language::De);
+    }
+
+    #[test]
+    fn detects_english() {
+        assert_eq!(detect("What is the deadline for the report?"), Language::En);
+        assert_eq!(
+            detect("Does the contract renew automatically?"),
+            Language::En
+        );
+    }
+
+    #[test]
+    fn detects_the_dominant_language_of_mixed_text() {
+        assert_eq!(detect("Wie übersetzt man 'the deadline'?"), Language::De);
+        assert_eq!(
+            detect("What does 'die Kündigung' mean in the contract?"),
+            Language::En
+        );
+    }
+
+    #[test]
+    fn defaults_to_english() {
+        assert_eq!(detect(""), Language::En);
+        assert_eq!(detect("RAG 2024"), Language::En);
+    }
+}
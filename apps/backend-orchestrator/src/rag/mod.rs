@@ -1,5 +1,21 @@
-// pub mod generation;
-// pub mod retrieval;
+pub mod agent;
+pub mod citations;
+pub mod context;
+pub mod filters;
+pub mod follow_ups;
+pub mod generation;
+pub mod language;
+pub mod pipeline;
+pub mod prompts;
+pub mod retrieval;
+pub mod screening;
+pub mod summary;
 
-// pub use generation::GenerationService;
-// pub use retrieval::RetrievalService;
+pub use agent::ChatAgent;
+pub use context::{BuiltContext, ContextBuilder};
+pub use generation::GenerationService;
+pub use pipeline::{ChatPipeline, ChatTurn, PromptPreview};
+pub use prompts::{PromptTemplates, PromptVariables, RenderedPrompt};
+pub use retrieval::{RetrievalParams, RetrievalService};
+pub use screening::{InjectionScreener, ScreeningAction};
+pub use summary::DocumentSummarizer;
@@ -0,0 +1,500 @@
+use crate::error::Result;
+use crate::models::{
+    ChatMessage, ChatStreamEvent, GenerationParams, Language, SearchExplanation, SearchResult,
+};
+use crate::rag::{
+    BuiltContext, ChatAgent, ContextBuilder, GenerationService, PromptVariables, RenderedPrompt,
+    RetrievalParams, RetrievalService, citations, follow_ups, language,
+};
+use crate::services::answer_cache::{CacheScope, CachedAnswer};
+use crate::services::llm::StreamToken;
+use crate::services::{AnswerCache, SessionStore};
+use futures::{Stream, StreamExt};
+use serde::Serialize;
+use std::env;
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
+
+/// Input of a single chat turn.
+pub struct ChatTurn {
+    pub message: String,
+    /// Session to load the history from and to store the turn in. Without a
+    /// session, `history` is used as given and nothing is stored.
+    pub session_id: Option<String>,
+    pub history: Vec<ChatMessage>,
+    /// Validated with [`RetrievalService::resolve_params`]
+    pub retrieval: RetrievalParams,
+    /// Prompt template to use instead of the automatic selection
+    pub prompt_template: Option<String>,
+    /// Answer language, detected from the message if not set
+    pub language: Option<Language>,
+    /// Validated with [`GenerationService::resolve_params`]
+    pub generation: GenerationParams,
+    /// Run the turn with [`ChatAgent`] instead of a single retrieval, without
+    /// answer cache and relevance threshold
+    pub agent: bool,
+    /// Suggest follow-up questions after the answer, the pipeline's default if not set
+    pub follow_ups: Option<bool>,
+}
+
+/// The prompt a chat turn would send to the LLM, see [`ChatPipeline::preview`].
+#[derive(Debug, Serialize)]
+pub struct PromptPreview {
+    pub query: String,
+    pub sources: Vec<SearchResult>,
+    pub prompt: RenderedPrompt,
+    pub generation: GenerationParams,
+}
+
+/// Full RAG loop for one chat turn: condense → retrieve → screen → build
+/// context → generate → verify citations, emitted as a stream of [`ChatStreamEvent`]s.
+pub struct ChatPipeline {
+    retrieval_service: Arc<RetrievalService>,
+    generation_service: Arc<GenerationService>,
+    context_builder: Arc<ContextBuilder>,
+    session_store: Arc<SessionStore>,
+    agent: Arc<ChatAgent>,
+    answer_cache: Arc<AnswerCache>,
+    /// Minimum Meilisearch ranking score of the best hit to call the LLM at all
+    relevance_threshold: f64,
+    /// Suggest follow-up questions unless a turn opts out
+    follow_ups: bool,
+}
+
+impl ChatPipeline {
+    pub fn new(
+        retrieval_service: Arc<RetrievalService>,
+        generation_service: Arc<GenerationService>,
+        context_builder: Arc<ContextBuilder>,
+        session_store: Arc<SessionStore>,
+        agent: Arc<ChatAgent>,
+        answer_cache: Arc<AnswerCache>,
+    ) -> Self {
+        let relevance_threshold = env::var("RELEVANCE_SCORE_THRESHOLD")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(0.2);
+        let follow_ups = env::var("FOLLOW_UP_QUESTIONS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(true);
+
+        Self {
+            retrieval_service,
+            generation_service,
+            context_builder,
+            session_store,
+            agent,
+            answer_cache,
+            relevance_threshold,
+            follow_ups,
+        }
+    }
+
+    /// Runs a chat turn. Cancelling `cancel` stops the generation and drops the
+    /// upstream completion request; the partial answer is kept.
+    pub fn run(
+        self: Arc<Self>,
+        turn: ChatTurn,
+        cancel: CancellationToken,
+    ) -> impl Stream<Item = ChatStreamEvent> + use<> {
+        async_stream::stream! {
+            if turn.agent {
+                // The agent retrieves through its tools, so the answer cache and the
+                // relevance threshold do not apply; follow-ups are suggested from the
+                // sources its tool calls returned.
+                let follow_ups = turn.follow_ups.unwrap_or(self.follow_ups);
+                let message = turn.message.clone();
+                let language = turn.language.unwrap_or_else(|| language::detect(&message));
+                let mut answer = String::new();
+                let mut sources: Vec<SearchResult> = Vec::new();
+                let mut failed = false;
+
+                for await event in self.agent.clone().run(turn, cancel.clone()) {
+                    match &event {
+                        ChatStreamEvent::Token { content } => answer.push_str(content),
+                        ChatStreamEvent::ToolResult { sources: new_sources, .. } => {
+                            sources.extend(new_sources.iter().cloned());
+                        }
+                        ChatStreamEvent::Error { .. } => failed = true,
+                        ChatStreamEvent::Done { cancelled: false, .. }
+                            if follow_ups && !failed && !answer.is_empty() =>
+                        {
+                            if let Some(questions) = self
+                                .follow_up_questions(&message, &answer, &sources, language, &cancel)
+                                .await
+                            {
+                                yield ChatStreamEvent::FollowUps { questions };
+                            }
+                        }
+                        _ => {}
+                    }
+                    yield event;
+                }
+                return;
+            }
+
+            let ChatTurn { message, session_id, history, mut retrieval, prompt_template, language, generation, follow_ups, .. } = turn;
+            let language = language.unwrap_or_else(|| language::detect(&message));
+            retrieval.boost_language = Some(language);
+            let follow_ups = follow_ups.unwrap_or(self.follow_ups);
+
+            let history = match &session_id {
+                Some(session_id) => self.session_store.history(session_id).await,
+                None => history,
+            };
+
+            let query = self
+                .retrieval_service
+                .condense_query(&history, &message)
+                .await;
+            yield ChatStreamEvent::Query { query: query.clone() };
+
+            let scope = CacheScope {
+                retrieval_mode: retrieval.mode,
+                top_k: retrieval.top_k,
+                semantic_ratio: retrieval.semantic_ratio,
+                filter: retrieval.filter.clone(),
+                neighbor_chunks: retrieval.neighbor_chunks,
+                language,
+                prompt_template: prompt_template.clone(),
+                generation: generation.clone(),
+            };
+            let embedding = self.query_embedding(&query).await;
+            // Taken before retrieval, an invalidation from now on discards the answer
+            let cache_epoch = self.answer_cache.epoch();
+            if let Some(embedding) = &embedding
+                && let Some(cached) = self.answer_cache.get(embedding, &scope).await
+            {
+                yield ChatStreamEvent::Sources { sources: cached.sources.clone() };
+                yield ChatStreamEvent::Token { content: cached.answer.clone() };
+
+                // Replayed from the cache unless the answer was cached by a turn
+                // that did not ask for follow-ups
+                if follow_ups {
+                    let questions = match cached.follow_ups.clone() {
+                        Some(questions) => Some(questions),
+                        None => {
+                            self.follow_up_questions(&message, &cached.answer, &cached.sources, language, &cancel)
+                                .await
+                        }
+                    };
+                    if let Some(questions) = questions {
+                        yield ChatStreamEvent::FollowUps { questions };
+                    }
+                }
+
+                if let Some(session_id) = &session_id {
+                    self.session_store
+                        .append_turn(session_id, &message, &cached.answer, cached.sources, cached.citations.clone())
+                        .await;
+                }
+
+                yield ChatStreamEvent::Done { session_id, citations: cached.citations, generation: Some(generation), cancelled: false, truncated: false, cached: true };
+                return;
+            }
+
+            let results = match self
+                .retrieval_service
+                .retrieve(&query, &retrieval)
+                .await
+            {
+                Ok(results) => results,
+                Err(err) => {
+                    tracing::error!("Retrieval failed: {}", err);
+                    yield ChatStreamEvent::Error { message: format!("Search error: {}", err) };
+                    yield ChatStreamEvent::Done { session_id, citations: Vec::new(), generation: None, cancelled: false, truncated: false, cached: false };
+                    return;
+                }
+            };
+
+            let top_score = results
+                .iter()
+                .filter_map(|result| result.ranking_score)
+                .reduce(f64::max);
+            if top_score.is_none_or(|score| score < self.relevance_threshold) {
+                tracing::warn!(
+                    target: "unanswered_queries",
+                    query = %query,
+                    top_score = ?top_score,
+                    "No relevant chunks above threshold {}, abstaining",
+                    self.relevance_threshold
+                );
+
+                let answer = abstention_answer(language).to_owned();
+                yield ChatStreamEvent::Sources { sources: results.clone() };
+                yield ChatStreamEvent::Token { content: answer.clone() };
+
+                if let Some(session_id) = &session_id {
+                    self.session_store
+                        .append_turn(session_id, &message, &answer, results, Vec::new())
+                        .await;
+                }
+
+                yield ChatStreamEvent::Done { session_id, citations: Vec::new(), generation: None, cancelled: false, truncated: false, cached: false };
+                return;
+            }
+
+            let BuiltContext { context, history, sources } =
+                self.context_builder.build(&history, results);
+            yield ChatStreamEvent::Sources { sources: sources.clone() };
+
+            let variables = PromptVariables {
+                context: &context,
+                question: &message,
+                history: &history,
+                language,
+                sources: &sources,
+            };
+            let prompt = match self
+                .generation_service
+                .render_prompt(prompt_template.as_deref(), &variables)
+            {
+                Ok(prompt) => prompt,
+                Err(err) => {
+                    tracing::error!("Prompt rendering failed: {}", err);
+                    yield ChatStreamEvent::Error { message: err.to_string() };
+                    yield ChatStreamEvent::Done { session_id, citations: Vec::new(), generation: None, cancelled: false, truncated: false, cached: false };
+                    return;
+                }
+            };
+
+            if cancel.is_cancelled() {
+                yield ChatStreamEvent::Done { session_id, citations: Vec::new(), generation: None, cancelled: true, truncated: false, cached: false };
+                return;
+            }
+
+            let mut answer = String::new();
+            let mut failed = false;
+            let mut truncated = false;
+            match self
+                .generation_service
+                .generate_response_stream(&prompt, &generation)
+                .await
+            {
+                Ok(tokens) => {
+                    let mut tokens = std::pin::pin!(tokens);
+                    while let Some(token) = tokio::select! {
+                        biased;
+                        _ = cancel.cancelled() => None,
+                        token = tokens.next() => token,
+                    } {
+                        match token {
+                            Ok(StreamToken::Text(content)) if content.is_empty() => {}
+                            Ok(StreamToken::Text(content)) => {
+                                answer.push_str(&content);
+                                yield ChatStreamEvent::Token { content };
+                            }
+                            Ok(StreamToken::Truncated) => truncated = true,
+                            Err(err) => {
+                                tracing::error!("Generation failed mid-stream: {}", err);
+                                yield ChatStreamEvent::Error { message: err.to_string() };
+                                failed = true;
+                                break;
+                            }
+                        }
+                    }
+                }
+                Err(err) => {
+                    tracing::error!("Generation failed: {}", err);
+                    yield ChatStreamEvent::Error { message: err.to_string() };
+                    failed = true;
+                }
+            }
+
+            let cancelled = cancel.is_cancelled();
+            if cancelled {
+                tracing::info!("Generation cancelled after {} characters", answer.len());
+            }
+
+            let citations = citations::extract_citations(&answer, &sources);
+
+            let follow_up_questions = if follow_ups
+                && !cancelled
+                && !failed
+                && !answer.is_empty()
+            {
+                self.follow_up_questions(&message, &answer, &sources, language, &cancel).await
+            } else {
+                None
+            };
+
+            // Only complete answers are cached
+            if let Some(embedding) = embedding
+                && !cancelled
+                && !failed
+                && !truncated
+                && !answer.is_empty()
+            {
+                self.answer_cache
+                    .insert(cache_epoch, embedding, scope, CachedAnswer {
+                        query: query.clone(),
+                        answer: answer.clone(),
+                        sources: sources.clone(),
+                        citations: citations.clone(),
+                        follow_ups: follow_up_questions.clone(),
+                    })
+                    .await;
+            }
+
+            if let Some(questions) = follow_up_questions {
+                yield ChatStreamEvent::FollowUps { questions };
+            }
+
+            if let Some(session_id) = &session_id
+                && !answer.is_empty()
+            {
+                self.session_store
+                    .append_turn(session_id, &message, &answer, sources, citations.clone())
+                    .await;
+            }
+
+            yield ChatStreamEvent::Done { session_id, citations, generation: Some(generation), cancelled, truncated, cached: false };
+        }
+    }
+
+    /// Runs condensation, retrieval and context building for a turn like
+    /// [`ChatPipeline::run`] and returns the rendered prompt instead of
+    /// generating an answer. Nothing is stored in the session.
+    pub async fn preview(&self, turn: ChatTurn) -> Result<PromptPreview> {
+        let ChatTurn {
+            message,
+            session_id,
+            history,
+            mut retrieval,
+            prompt_template,
+            language,
+            generation,
+            ..
+        } = turn;
+        let language = language.unwrap_or_else(|| language::detect(&message));
+        retrieval.boost_language = Some(language);
+
+        let history = match &session_id {
+            Some(session_id) => self.session_store.history(session_id).await,
+            None => history,
+        };
+
+        let query = self
+            .retrieval_service
+            .condense_query(&history, &message)
+            .await;
+        let results = self.retrieval_service.retrieve(&query, &retrieval).await?;
+        let BuiltContext {
+            context,
+            history,
+            sources,
+        } = self.context_builder.build(&history, results);
+
+        let prompt = self.generation_service.render_prompt(
+            prompt_template.as_deref(),
+            &PromptVariables {
+                context: &context,
+                question: &message,
+                history: &history,
+                language,
+                sources: &sources,
+            },
+        )?;
+
+        Ok(PromptPreview {
+            query,
+            sources,
+            prompt,
+            generation,
+        })
+    }
+
+    /// Explains the retrieval of `query` hit by hit, including whether the
+    /// relevance threshold would make the chat abstain and which hits end up
+    /// in the context. The query is used as is, without condensation.
+    pub async fn explain(
+        &self,
+        query: &str,
+        mut retrieval: RetrievalParams,
+        language: Option<Language>,
+    ) -> Result<SearchExplanation> {
+        retrieval.boost_language = Some(language.unwrap_or_else(|| language::detect(query)));
+
+        let (mut explanation, results) = self.retrieval_service.explain(query, &retrieval).await?;
+        explanation.relevance_threshold = self.relevance_threshold;
+        explanation.abstained = explanation
+            .top_score
+            .is_none_or(|score| score < self.relevance_threshold);
+
+        if !explanation.abstained {
+            let BuiltContext { sources, .. } = self.context_builder.build(&[], results);
+            for hit in &mut explanation.hits {
+                hit.in_context = sources
+                    .iter()
+                    .any(|source| source.id == hit.id || source.merged_chunk_ids.contains(&hit.id));
+            }
+        }
+
+        Ok(explanation)
+    }
+
+    /// Embeds the query for the answer cache; `None` if the cache is disabled
+    /// or the embedding failed, in which case the cache is bypassed.
+    async fn query_embedding(&self, query: &str) -> Option<Vec<f32>> {
+        if !self.answer_cache.is_enabled() {
+            return None;
+        }
+
+        self.retrieval_service
+            .embedding_service
+            .embed(query)
+            .await
+            .inspect_err(|err| tracing::warn!("Answer cache bypassed, embedding failed: {}", err))
+            .ok()
+    }
+
+    /// Suggests questions answered by the sources the answer did not fully
+    /// cover. `None` if all sources were covered, the generation failed or
+    /// too few questions came back.
+    async fn follow_up_questions(
+        &self,
+        question: &str,
+        answer: &str,
+        sources: &[SearchResult],
+        language: Language,
+        cancel: &CancellationToken,
+    ) -> Option<Vec<String>> {
+        let uncovered = follow_ups::uncovered_sources(answer, sources);
+        if uncovered.is_empty() {
+            return None;
+        }
+
+        let prompt = follow_ups::build_prompt(question, answer, &uncovered, language);
+        let response = tokio::select! {
+            biased;
+            _ = cancel.cancelled() => return None,
+            response = self.generation_service.complete(follow_ups::FOLLOW_UP_SYSTEM_PROMPT, &prompt, 0.5, 256) => response,
+        };
+
+        match response {
+            Ok(response) => {
+                let questions = follow_ups::parse_questions(&response);
+                (questions.len() >= follow_ups::MIN_QUESTIONS).then_some(questions)
+            }
+            Err(err) => {
+                tracing::warn!("Generating follow-up questions failed: {}", err);
+                None
+            }
+        }
+    }
+}
+
+/// Answer returned instead of a generation when nothing relevant was retrieved.
+fn abstention_answer(language: Language) -> &'static str {
+    if language == Language::De {
+        "Dazu habe ich in der Wissensdatenbank keine passenden Informationen gefunden. \
+         Bitte formuliere die Frage um oder lade die entsprechenden Dokumente hoch."
+    } else {
+        "I could not find information about this in the knowledge base. \
+         Please rephrase the question or upload the relevant documents."
+    }
+}
+
+#[cfg(test)]
+mod tests;
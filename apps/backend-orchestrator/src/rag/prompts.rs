@@ -0,0 +1,531 @@
+use crate::error::{RagError, Result};
+use crate::models::{ChatMessage, ChatRole, Language, SearchResult};
+use regex::{Captures, Regex};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, RwLock};
+use std::{env, fs};
+
+const DEFAULT_TEMPLATE: &str = "default";
+
+/// Built-in `default` templates, used when the template directory does not override them.
+const DEFAULT_SYSTEM: &str = include_str!("../../prompts/default.system.md");
+const DEFAULT_USER: &str = include_str!("../../prompts/default.user.md");
+const DEFAULT_DE_SYSTEM: &str = include_str!("../../prompts/default.de.system.md");
+const DEFAULT_DE_USER: &str = include_str!("../../prompts/default.de.user.md");
+
+/// Built-in template of the agent mode, see [`crate::rag::agent`].
+pub const AGENT_TEMPLATE: &str = "agent";
+const AGENT_SYSTEM: &str = include_str!("../../prompts/agent.system.md");
+const AGENT_USER: &str = include_str!("../../prompts/agent.user.md");
+
+const SYSTEM_SUFFIX: &str = ".system.md";
+const USER_SUFFIX: &str = ".user.md";
+
+/// Variables that can be used in templates as `{{name}}`.
+const VARIABLES: &[&str] = &[
+    "context",
+    "question",
+    "history",
+    "language",
+    "document_titles",
+];
+
+static VARIABLE_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\{\{\s*([A-Za-z_]+)\s*\}\}").expect("template variable regex is valid")
+});
+
+#[derive(Debug, Clone)]
+struct PromptTemplate {
+    system: String,
+    user: String,
+}
+
+impl PromptTemplate {
+    /// Templates that inline `{{history}}` get no separate history messages.
+    fn uses_history(&self) -> bool {
+        VARIABLE_RE
+            .captures_iter(&self.system)
+            .chain(VARIABLE_RE.captures_iter(&self.user))
+            .any(|variable| &variable[1] == "history")
+    }
+}
+
+/// Values of the template variables for one chat turn.
+pub struct PromptVariables<'a> {
+    pub context: &'a str,
+    pub question: &'a str,
+    pub history: &'a [ChatMessage],
+    pub language: Language,
+    pub sources: &'a [SearchResult],
+}
+
+/// A template rendered for one chat turn, exactly as sent to the LLM.
+#[derive(Debug, Clone, Serialize)]
+pub struct RenderedPrompt {
+    pub template: String,
+    pub system: String,
+    /// History sent as separate chat messages, empty if the template inlines `{{history}}`
+    pub history: Vec<ChatMessage>,
+    pub user: String,
+}
+
+/// Named prompt templates loaded from `PROMPT_TEMPLATES_DIR` (default `prompts`).
+///
+/// A template `name` consists of `name.system.md` and an optional `name.user.md`;
+/// without a user part, the user part of the built-in `default` template is used.
+/// Localized variants are named after the language code, e.g. `name.de.system.md`,
+/// and take precedence over `name` for answers in that language.
+pub struct PromptTemplates {
+    directory: PathBuf,
+    templates: RwLock<HashMap<String, PromptTemplate>>,
+}
+
+impl PromptTemplates {
+    pub fn new() -> Self {
+        let directory = env::var("PROMPT_TEMPLATES_DIR").unwrap_or_else(|_| "prompts".to_string());
+
+        Self::with_directory(PathBuf::from(directory))
+    }
+
+    pub fn with_directory(directory: PathBuf) -> Self {
+        let prompt_templates = Self {
+            directory,
+            templates: RwLock::new(builtin_templates()),
+        };
+        if let Err(err) = prompt_templates.reload() {
+            tracing::error!(
+                "Failed to load prompt templates, using built-in default: {}",
+                err
+            );
+        }
+
+        prompt_templates
+    }
+
+    /// Re-reads the template directory and returns the names of the loaded
+    /// templates. On error, the previously loaded templates stay active.
+    pub fn reload(&self) -> Result<Vec<String>> {
+        let loaded = load_directory(&self.directory)?;
+        *self
+            .templates
+            .write()
+            .expect("prompt templates lock poisoned") = loaded;
+
+        let names = self.names();
+        tracing::info!(
+            "Loaded prompt templates from {}: {:?}",
+            self.directory.display(),
+            names
+        );
+        Ok(names)
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        sorted_names(
+            &self
+                .templates
+                .read()
+                .expect("prompt templates lock poisoned"),
+        )
+    }
+
+    /// Checks that a requested template can be rendered for an answer in
+    /// `language`, i.e. that it exists localized for `language` or unlocalized.
+    pub fn validate(&self, name: Option<&str>, language: Language) -> Result<()> {
+        let Some(name) = name else {
+            return Ok(());
+        };
+        let templates = self
+            .templates
+            .read()
+            .expect("prompt templates lock poisoned");
+
+        if resolve(&templates, &[name], language).is_some() {
+            return Ok(());
+        }
+        Err(RagError::InvalidRequest(format!(
+            "Unknown prompt template '{}' for language '{}'. Available templates: {}",
+            name,
+            language.code(),
+            // Not `self.names()`, a second read lock could deadlock with a waiting reload
+            sorted_names(&templates).join(", ")
+        )))
+    }
+
+    /// Renders the requested template, or else the template named after the
+    /// `document_type` of the best source, or else `default`, each preferring
+    /// the variant localized for `variables.language`.
+    pub fn render(
+        &self,
+        requested: Option<&str>,
+        variables: &PromptVariables,
+    ) -> Result<RenderedPrompt> {
+        let templates = self
+            .templates
+            .read()
+            .expect("prompt templates lock poisoned");
+
+        let candidates = match requested {
+            Some(name) => vec![name],
+            None => variables
+                .sources
+                .first()
+                .and_then(|source| source.metadata.document_type.as_deref())
+                .into_iter()
+                .chain([DEFAULT_TEMPLATE])
+                .collect(),
+        };
+        let (name, template) =
+            resolve(&templates, &candidates, variables.language).ok_or_else(|| {
+                RagError::InvalidRequest(format!("Unknown prompt template '{}'", candidates[0]))
+            })?;
+
+        let values = HashMap::from([
+            ("context", variables.context.to_owned()),
+            ("question", variables.question.to_owned()),
+            ("history", format_history(variables.history)),
+            ("language", variables.language.name().to_owned()),
+            ("document_titles", document_titles(variables.sources)),
+        ]);
+        let substitute = |text: &str| {
+            VARIABLE_RE
+                .replace_all(text, |variable: &Captures| {
+                    values
+                        .get(&variable[1])
+                        .cloned()
+                        .unwrap_or_else(|| variable[0].to_owned())
+                })
+                .into_owned()
+        };
+
+        Ok(RenderedPrompt {
+            template: name,
+            system: substitute(&template.system),
+            history: if template.uses_history() {
+                Vec::new()
+            } else {
+                variables.history.to_vec()
+            },
+            user: substitute(&template.user),
+        })
+    }
+}
+
+/// The first of `candidates` that exists, each localized for `language` or else unlocalized.
+fn resolve<'a>(
+    templates: &'a HashMap<String, PromptTemplate>,
+    candidates: &[&str],
+    language: Language,
+) -> Option<(String, &'a PromptTemplate)> {
+    candidates
+        .iter()
+        .flat_map(|name| [format!("{}.{}", name, language.code()), name.to_string()])
+        .find_map(|name| templates.get(&name).map(|template| (name, template)))
+}
+
+fn builtin_templates() -> HashMap<String, PromptTemplate> {
+    HashMap::from([
+        (
+            DEFAULT_TEMPLATE.to_owned(),
+            PromptTemplate {
+                system: DEFAULT_SYSTEM.trim_end().to_owned(),
+                user: DEFAULT_USER.trim_end().to_owned(),
+            },
+        ),
+        (
+            format!("{}.{}", DEFAULT_TEMPLATE, Language::De.code()),
+            PromptTemplate {
+                system: DEFAULT_DE_SYSTEM.trim_end().to_owned(),
+                user: DEFAULT_DE_USER.trim_end().to_owned(),
+            },
+        ),
+        (
+            AGENT_TEMPLATE.to_owned(),
+            PromptTemplate {
+                system: AGENT_SYSTEM.trim_end().to_owned(),
+                user: AGENT_USER.trim_end().to_owned(),
+            },
+        ),
+    ])
+}
+
+fn sorted_names(templates: &HashMap<String, PromptTemplate>) -> Vec<String> {
+    let mut names: Vec<String> = templates.keys().cloned().collect();
+    names.sort();
+    names
+}
+
+fn load_directory(directory: &Path) -> Result<HashMap<String, PromptTemplate>> {
+    let mut templates = builtin_templates();
+
+    if !directory.is_dir() {
+        tracing::warn!(
+            "Prompt template directory {} not found, using built-in default",
+            directory.display()
+        );
+        return Ok(templates);
+    }
+
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        let Some(name) = path
+            .file_name()
+            .and_then(|file_name| file_name.to_str())
+            .and_then(|file_name| file_name.strip_suffix(SYSTEM_SUFFIX))
+        else {
+            continue;
+        };
+
+        let system = fs::read_to_string(&path)?;
+        // A localized template without its own user template uses the
+        // unlocalized one, or else the built-in one of its language
+        let localized = [Language::De, Language::En]
+            .into_iter()
+            .find_map(|language| {
+                name.strip_suffix(&format!(".{}", language.code()))
+                    .map(|base| (base, language))
+            });
+        let user_path = [Some(name), localized.map(|(base, _)| base)]
+            .into_iter()
+            .flatten()
+            .map(|name| directory.join(format!("{}{}", name, USER_SUFFIX)))
+            .find(|path| path.is_file());
+        let user = match (user_path, localized) {
+            (Some(user_path), _) => fs::read_to_string(&user_path)?,
+            (None, Some((_, Language::De))) => DEFAULT_DE_USER.to_owned(),
+            (None, _) => DEFAULT_USER.to_owned(),
+        };
+
+        for variable in VARIABLE_RE
+            .captures_iter(&system)
+            .chain(VARIABLE_RE.captures_iter(&user))
+        {
+            if !VARIABLES.contains(&&variable[1]) {
+                tracing::warn!(
+                    "Prompt template '{}' uses unknown variable {}, known variables: {:?}",
+                    name,
+                    &variable[0],
+                    VARIABLES
+                );
+            }
+        }
+
+        templates.insert(
+            name.to_owned(),
+            PromptTemplate {
+                system: system.trim_end().to_owned(),
+                user: user.trim_end().to_owned(),
+            },
+        );
+    }
+
+    Ok(templates)
+}
+
+fn format_history(history: &[ChatMessage]) -> String {
+    history
+        .iter()
+        .map(|message| {
+            let speaker = match message.role {
+                ChatRole::User => "User",
+                ChatRole::Assistant => "Assistant",
+            };
+            format!("{}: {}", speaker, message.content)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn document_titles(sources: &[SearchResult]) -> String {
+    let mut titles: Vec<&str> = Vec::new();
+    for source in sources {
+        if !titles.contains(&source.title.as_str()) {
+            titles.push(&source.title);
+        }
+    }
+    titles.join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::DocumentMetadata;
+
+    struct TempDirectory(PathBuf);
+
+    impl TempDirectory {
+        fn new(name: &str, files: &[(&str, &str)]) -> Self {
+            let path = env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            let directory = Self(path);
+            directory.write(files);
+            directory
+        }
+
+        fn write(&self, files: &[(&str, &str)]) {
+            for (name, content) in files {
+                fs::write(self.0.join(name), content).unwrap();
+            }
+        }
+    }
+
+    impl Drop for TempDirectory {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn source(document_type: Option<&str>) -> SearchResult {
+        SearchResult {
+            id: "chunk".to_owned(),
+            title: "Lease.md".to_owned(),
+            content: String::new(),
+            metadata: DocumentMetadata {
+                document_type: document_type.map(str::to_owned),
+                ..DocumentMetadata::default()
+            },
+            source_file: None,
+            document_id: None,
+            chunk_index: None,
+            rerank_score: None,
+            ranking_score: None,
+            merged_chunk_ids: Vec::new(),
+        }
+    }
+
+    fn render(
+        templates: &PromptTemplates,
+        requested: Option<&str>,
+        language: Language,
+        sources: &[SearchResult],
+    ) -> Result<RenderedPrompt> {
+        templates.render(
+            requested,
+            &PromptVariables {
+                context: "the context",
+                question: "the question",
+                history: &[],
+                language,
+                sources,
+            },
+        )
+    }
+
+    #[test]
+    fn prefers_the_localized_variant() {
+        let directory = TempDirectory::new(
+            "prompts-localized",
+            &[
+                ("legal.system.md", "Legal, {{language}}."),
+                ("legal.de.system.md", "Juristisch, {{language}}."),
+            ],
+        );
+        let templates = PromptTemplates::with_directory(directory.0.clone());
+
+        let german = render(&templates, Some("legal"), Language::De, &[]).unwrap();
+        let english = render(&templates, Some("legal"), Language::En, &[]).unwrap();
+
+        assert_eq!(
+            (german.template.as_str(), german.system.as_str()),
+            ("legal.de", "Juristisch, German.")
+        );
+        assert_eq!(
+            (english.template.as_str(), english.system.as_str()),
+            ("legal", "Legal, English.")
+        );
+        assert_eq!(
+            english.user,
+            "Context:\nthe context\n\nUser Question: the question"
+        );
+    }
+
+    #[test]
+    fn localized_templates_fall_back_to_a_user_template_of_their_language() {
+        let directory = TempDirectory::new(
+            "prompts-localized-user",
+            &[
+                ("legal.de.system.md", "Juristisch."),
+                ("faq.de.system.md", "FAQ."),
+                ("faq.user.md", "{{context}} / {{question}}"),
+            ],
+        );
+        let templates = PromptTemplates::with_directory(directory.0.clone());
+
+        let legal = render(&templates, Some("legal"), Language::De, &[]).unwrap();
+        let faq = render(&templates, Some("faq"), Language::De, &[]).unwrap();
+
+        assert_eq!(legal.user, "Kontext:\nthe context\n\nFrage: the question");
+        assert_eq!(faq.user, "the context / the question");
+    }
+
+    #[test]
+    fn selects_the_template_by_document_type() {
+        let directory =
+            TempDirectory::new("prompts-document-type", &[("lease.system.md", "Lease.")]);
+        let templates = PromptTemplates::with_directory(directory.0.clone());
+
+        let template = |sources: &[SearchResult], language| {
+            render(&templates, None, language, sources)
+                .unwrap()
+                .template
+        };
+
+        assert_eq!(template(&[source(Some("lease"))], Language::En), "lease");
+        assert_eq!(
+            template(&[source(Some("invoice"))], Language::En),
+            "default"
+        );
+        assert_eq!(template(&[source(None)], Language::De), "default.de");
+        assert_eq!(template(&[], Language::En), "default");
+    }
+
+    #[test]
+    fn rejects_templates_missing_for_the_language() {
+        let directory =
+            TempDirectory::new("prompts-validate", &[("legal.de.system.md", "Juristisch.")]);
+        let templates = PromptTemplates::with_directory(directory.0.clone());
+
+        assert!(templates.validate(Some("legal"), Language::De).is_ok());
+        assert!(templates.validate(Some("legal"), Language::En).is_err());
+        assert!(templates.validate(Some("missing"), Language::De).is_err());
+        assert!(templates.validate(None, Language::En).is_ok());
+        assert!(render(&templates, Some("missing"), Language::En, &[]).is_err());
+    }
+
+    #[test]
+    fn lists_available_templates_for_unknown_names() {
+        let directory = TempDirectory::new("prompts-unknown", &[("faq.system.md", "FAQ.")]);
+        let templates = PromptTemplates::with_directory(directory.0.clone());
+
+        let Err(RagError::InvalidRequest(message)) =
+            templates.validate(Some("missing"), Language::En)
+        else {
+            panic!("expected an invalid request");
+        };
+
+        assert_eq!(
+            message,
+            "Unknown prompt template 'missing' for language 'en'. \
+             Available templates: agent, default, default.de, faq"
+        );
+    }
+
+    #[test]
+    fn reloads_changed_templates() {
+        let directory = TempDirectory::new("prompts-reload", &[("faq.system.md", "First.")]);
+        let templates = PromptTemplates::with_directory(directory.0.clone());
+        assert_eq!(templates.names(), ["agent", "default", "default.de", "faq"]);
+
+        directory.write(&[("faq.system.md", "Second."), ("news.system.md", "News.")]);
+        assert_eq!(
+            templates.reload().unwrap(),
+            ["agent", "default", "default.de", "faq", "news"]
+        );
+
+        let rendered = render(&templates, Some("faq"), Language::En, &[]).unwrap();
+        assert_eq!(rendered.system, "Second.");
+    }
+}
@@ -1,18 +1,134 @@
 use crate::error::RagError;
-use crate::models::SearchResult;
-use crate::services::MeilisearchService;
+use crate::models::{
+    ChatMessage, ChatRole, HitExplanation, HitOutcome, HitScreening, Language, RetrievalMode,
+    RetrievalOptions, ScreeningOutcome, SearchExplanation, SearchResult,
+};
+use crate::rag::context::join_overlapping;
+use crate::rag::screening::InjectionFinding;
+use crate::rag::{InjectionScreener, ScreeningAction, filters};
+use crate::services::meilisearch::FILTERABLE_ATTRIBUTES;
+use crate::services::{EmbeddingService, LlmService, MeilisearchService, RerankerService};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+/// Number of chunks retrieved when the request does not set `top_k`.
+const DEFAULT_TOP_K: usize = 5;
+
+/// Upper bound for `top_k`, to keep the context and reranking cost bounded.
+const MAX_TOP_K: usize = 50;
+
+const DEFAULT_SEMANTIC_RATIO: f32 = 0.5;
+
+/// Upper bound for `neighbor_chunks`.
+const MAX_NEIGHBOR_CHUNKS: usize = 5;
+
+/// Number of paraphrases generated in `RetrievalMode::MultiQuery`.
+const MULTI_QUERY_VARIANTS: usize = 3;
+
+/// Added to the ranking score of chunks in the answer language before ordering.
+const LANGUAGE_BOOST: f64 = 0.05;
+
+/// Rank constant of reciprocal rank fusion, 60 as proposed by Cormack et al.
+const RRF_K: f64 = 60.0;
+
+const CONDENSE_SYSTEM_PROMPT: &str = r#"You rewrite follow-up questions into standalone search queries.
+
+Given a conversation and a follow-up question, rewrite the follow-up question so that it can be understood without the conversation. Resolve pronouns and references like "the second one" using the conversation. Keep the language of the follow-up question.
+
+Reply with the rewritten question only, without explanations or quotes."#;
+
+const MULTI_QUERY_SYSTEM_PROMPT: &str = r#"You generate alternative phrasings of search queries for a document search engine.
+
+Rewrite the given query in different ways, using synonyms and the wording a technical document would use. Keep the language of the query.
+
+Reply with one query per line, without numbering, explanations or quotes."#;
+
+const HYDE_SYSTEM_PROMPT: &str = r#"You write passages for a technical knowledge base.
+
+Write a short passage (3-5 sentences) that answers the given question as a document in the knowledge base would. If you do not know the facts, write a plausible passage anyway; it is only used to find similar documents. Keep the language of the question.
+
+Reply with the passage only."#;
+
+/// Validated retrieval settings of a single request.
+#[derive(Debug, Clone)]
+pub struct RetrievalParams {
+    pub mode: RetrievalMode,
+    pub top_k: usize,
+    pub semantic_ratio: f32,
+    pub filter: Option<String>,
+    /// Chunks before and after each hit merged into its content
+    pub neighbor_chunks: usize,
+    /// Prefer chunks in this language, set by the chat pipeline
+    pub boost_language: Option<Language>,
+}
 
 pub struct RetrievalService {
-    // pub embedding_service: EmbeddingService,
-    pub meilisearch_service: MeilisearchService,
+    pub embedding_service: Arc<EmbeddingService>,
+    pub meilisearch_service: Arc<MeilisearchService>,
+    llm_service: Arc<LlmService>,
+    reranker_service: Arc<RerankerService>,
+    pub screener: Arc<InjectionScreener>,
 }
 
 impl RetrievalService {
-    pub fn new() -> Self {
-        let meilisearch_service = MeilisearchService::new();
-
+    pub fn new(
+        embedding_service: Arc<EmbeddingService>,
+        meilisearch_service: Arc<MeilisearchService>,
+        llm_service: Arc<LlmService>,
+        reranker_service: Arc<RerankerService>,
+        screener: Arc<InjectionScreener>,
+    ) -> Self {
         Self {
+            embedding_service,
             meilisearch_service,
+            llm_service,
+            reranker_service,
+            screener,
+        }
+    }
+
+    /// Rewrites the latest user message into a standalone query using the conversation
+    /// history. Without history, or if the LLM fails, the message is returned unchanged.
+    pub async fn condense_query(&self, history: &[ChatMessage], message: &str) -> String {
+        if history.is_empty() {
+            return message.to_owned();
+        }
+
+        let transcript = history
+            .iter()
+            .map(|turn| {
+                let speaker = match turn.role {
+                    ChatRole::User => "User",
+                    ChatRole::Assistant => "Assistant",
+                };
+                format!("{}: {}", speaker, turn.content)
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let prompt = format!(
+            "Conversation:\n{}\n\nFollow-up question: {}\n\nStandalone question:",
+            transcript, message
+        );
+
+        match self
+            .llm_service
+            .complete(CONDENSE_SYSTEM_PROMPT, &prompt, 0.0, 128)
+            .await
+        {
+            Ok(rewritten) => {
+                let rewritten = rewritten.trim().trim_matches('"').trim();
+                if rewritten.is_empty() {
+                    message.to_owned()
+                } else {
+                    tracing::info!("Condensed query '{}' to '{}'", message, rewritten);
+                    rewritten.to_owned()
+                }
+            }
+            Err(err) => {
+                tracing::warn!("Query condensation failed, using original message: {}", err);
+                message.to_owned()
+            }
         }
     }
 
@@ -20,14 +136,21 @@ impl RetrievalService {
         &self,
         query: &str,
         limit: usize,
-        filters: Option<&str>,
+        params: &RetrievalParams,
     ) -> Result<Vec<SearchResult>, meilisearch_sdk::errors::Error> {
         // Perform hybrid search in Meilisearch
-        let results = self
+        let mut results = self
             .meilisearch_service
-            .hybrid_search(query, limit, filters)
+            .hybrid_search(
+                query,
+                limit,
+                params.semantic_ratio,
+                params.filter.as_deref(),
+            )
             .await?;
 
+        boost_language(&mut results, params.boost_language);
+
         tracing::info!(
             "Retrieved {} relevant chunks for query: '{}'",
             results.len(),
@@ -37,42 +160,494 @@ impl RetrievalService {
         Ok(results)
     }
 
-    pub async fn retrieve_with_context(
+    /// Searches with the original query and LLM-generated paraphrases concurrently
+    /// and merges the result lists with reciprocal rank fusion.
+    async fn retrieve_multi_query(
         &self,
         query: &str,
         limit: usize,
-    ) -> Result<(Vec<SearchResult>, String), RagError> {
-        let results = self.retrieve_relevant_chunks(query, limit, None).await?;
+        params: &RetrievalParams,
+    ) -> Result<Vec<SearchResult>, RagError> {
+        let mut queries = vec![query.to_owned()];
+        queries.extend(self.generate_query_variants(query).await);
 
-        // Build context from retrieved chunks
-        let context = self.build_context(&results);
+        let searches = queries
+            .iter()
+            .map(|q| self.retrieve_relevant_chunks(q, limit, params));
+        let result_lists = futures::future::try_join_all(searches).await?;
 
-        Ok((results, context))
+        Ok(reciprocal_rank_fusion(result_lists, limit))
     }
 
-    fn build_context(&self, results: &[SearchResult]) -> String {
-        let mut context_parts = Vec::new();
+    async fn generate_query_variants(&self, query: &str) -> Vec<String> {
+        let prompt = format!(
+            "Query: {}\n\nWrite {} alternative queries:",
+            query, MULTI_QUERY_VARIANTS
+        );
 
-        for (i, result) in results.iter().enumerate() {
-            let mut chunk_context = format!("[Source {}: {}]\n", i + 1, result.source_file);
+        match self
+            .llm_service
+            .complete(MULTI_QUERY_SYSTEM_PROMPT, &prompt, 0.7, 256)
+            .await
+        {
+            Ok(response) => {
+                let variants: Vec<String> = response
+                    .lines()
+                    .map(|line| {
+                        line.trim()
+                            .trim_start_matches(|c: char| {
+                                c.is_ascii_digit() || matches!(c, '.' | ')' | '-' | '*')
+                            })
+                            .trim()
+                            .trim_matches('"')
+                            .to_owned()
+                    })
+                    .filter(|line| !line.is_empty() && line != query)
+                    .take(MULTI_QUERY_VARIANTS)
+                    .collect();
 
-            if let Some(h1) = &result.hierarchy_lvl1 {
-                chunk_context.push_str(&format!("Section: {}\n", h1));
+                tracing::info!("Generated query variants for '{}': {:?}", query, variants);
+                variants
             }
-            if let Some(h2) = &result.hierarchy_lvl2 {
-                chunk_context.push_str(&format!("Subsection: {}\n", h2));
+            Err(err) => {
+                tracing::warn!(
+                    "Query expansion failed, searching original query only: {}",
+                    err
+                );
+                Vec::new()
             }
-            if let Some(h3) = &result.hierarchy_lvl3 {
-                chunk_context.push_str(&format!("Sub-subsection: {}\n", h3));
+        }
+    }
+
+    /// Drafts a hypothetical answer passage and uses its embedding for the semantic
+    /// part of the hybrid search, while the keyword part still uses the query.
+    /// Falls back to a standard hybrid search if drafting or embedding fails.
+    async fn retrieve_hyde(
+        &self,
+        query: &str,
+        limit: usize,
+        params: &RetrievalParams,
+    ) -> Result<Vec<SearchResult>, RagError> {
+        let passage = match self
+            .llm_service
+            .complete(HYDE_SYSTEM_PROMPT, query, 0.3, 256)
+            .await
+        {
+            Ok(passage) => passage,
+            Err(err) => {
+                tracing::warn!("HyDE drafting failed, using standard search: {}", err);
+                return Ok(self.retrieve_relevant_chunks(query, limit, params).await?);
             }
+        };
+        tracing::debug!("HyDE passage for '{}': {}", query, passage);
 
-            chunk_context.push_str(&format!("Content: {}\n", result.content));
-            chunk_context.push_str(&format!("Relevance Score: {:.3}\n", result.score));
-            chunk_context.push_str("\n---\n\n");
+        let vector = match self.embedding_service.embed(&passage).await {
+            Ok(vector) => vector,
+            Err(err) => {
+                tracing::warn!("HyDE embedding failed, using standard search: {}", err);
+                return Ok(self.retrieve_relevant_chunks(query, limit, params).await?);
+            }
+        };
+
+        let mut results = self
+            .meilisearch_service
+            .hybrid_search_with_vector(
+                query,
+                &vector,
+                limit,
+                params.semantic_ratio,
+                params.filter.as_deref(),
+            )
+            .await?;
+        boost_language(&mut results, params.boost_language);
+
+        tracing::info!(
+            "Retrieved {} relevant chunks with HyDE for query: '{}'",
+            results.len(),
+            query
+        );
+
+        Ok(results)
+    }
+
+    /// Validates the per-request retrieval options against the index's
+    /// filterable attributes and resolves defaults.
+    pub fn resolve_params(&self, options: &RetrievalOptions) -> Result<RetrievalParams, RagError> {
+        filters::validate_options(options, MAX_TOP_K, MAX_NEIGHBOR_CHUNKS)?;
+        let filter = filters::build_filter(options, FILTERABLE_ATTRIBUTES)?;
+
+        Ok(RetrievalParams {
+            mode: options.retrieval_mode,
+            top_k: options.top_k.unwrap_or(DEFAULT_TOP_K),
+            semantic_ratio: options.semantic_ratio.unwrap_or(DEFAULT_SEMANTIC_RATIO),
+            filter,
+            neighbor_chunks: options.neighbor_chunks.unwrap_or(0),
+            boost_language: None,
+        })
+    }
+
+    /// Retrieves up to `params.top_k` chunks for `query` with the requested
+    /// strategy, reranked if a reranker is configured and screened for prompt
+    /// injections.
+    pub async fn retrieve(
+        &self,
+        query: &str,
+        params: &RetrievalParams,
+    ) -> Result<Vec<SearchResult>, RagError> {
+        // Over-fetch candidates when a reranker is configured
+        let candidates = self.reranker_service.candidate_limit(params.top_k);
+
+        let results = match params.mode {
+            RetrievalMode::Standard => {
+                self.retrieve_relevant_chunks(query, candidates, params)
+                    .await?
+            }
+            RetrievalMode::MultiQuery => {
+                self.retrieve_multi_query(query, candidates, params).await?
+            }
+            RetrievalMode::Hyde => self.retrieve_hyde(query, candidates, params).await?,
+        };
 
-            context_parts.push(chunk_context);
+        let results = self
+            .reranker_service
+            .rerank(query, results, params.top_k)
+            .await;
+
+        let results = if params.neighbor_chunks == 0 {
+            results
+        } else {
+            self.expand_neighbors(results, params.neighbor_chunks).await
+        };
+
+        // Screened last, so merged neighbor chunks are checked as well
+        Ok(self.screener.screen(results))
+    }
+
+    /// Replaces the content of each hit with the passage of up to `neighbors`
+    /// chunks before and after it, so the hit keeps its id for citations.
+    ///
+    /// Chunks already part of a better ranked passage are left out; a hit whose
+    /// own chunk is among them is dropped. Hits without `document_id` and
+    /// `chunk_index`, or whose neighbors can't be fetched, stay as they are.
+    async fn expand_neighbors(
+        &self,
+        results: Vec<SearchResult>,
+        neighbors: usize,
+    ) -> Vec<SearchResult> {
+        let windows = futures::future::join_all(results.iter().map(|result| async move {
+            let (Some(document_id), Some(index)) = (&result.document_id, result.chunk_index) else {
+                return Vec::new();
+            };
+            let range = index.saturating_sub(neighbors)..=index + neighbors;
+
+            self.meilisearch_service
+                .chunk_range(document_id, range)
+                .await
+                .inspect_err(|err| {
+                    tracing::warn!("Fetching neighbors of chunk {} failed: {}", result.id, err)
+                })
+                .unwrap_or_default()
+        }))
+        .await;
+
+        let mut used: HashSet<(String, usize)> = HashSet::new();
+        let mut expanded = Vec::new();
+
+        for (mut result, window) in results.into_iter().zip(windows) {
+            let key = result.document_id.clone().zip(result.chunk_index);
+            if let Some(key) = &key
+                && used.contains(key)
+            {
+                tracing::debug!("Chunk {} is part of a better ranked passage", result.id);
+                continue;
+            }
+            if window.is_empty() {
+                expanded.push(result);
+                continue;
+            }
+
+            let mut content = String::new();
+            for chunk in window {
+                let Some(chunk_key) = chunk.document_id.clone().zip(chunk.chunk_index) else {
+                    continue;
+                };
+                if !used.insert(chunk_key) {
+                    continue;
+                }
+                content = if content.is_empty() {
+                    chunk.content
+                } else {
+                    join_overlapping(&content, &chunk.content)
+                };
+            }
+            if let Some(key) = key {
+                used.insert(key);
+            }
+
+            if !content.is_empty() {
+                result.content = content;
+            }
+            expanded.push(result);
+        }
+
+        expanded
+    }
+
+    /// Runs the standard retrieval of `params` step by step and records for
+    /// every candidate its keyword and semantic scores and what language boost,
+    /// reranking and `top_k` did to it. Also returns the retrieval result.
+    ///
+    /// The relevance threshold and context of the chat pipeline are not
+    /// applied, see [`crate::rag::ChatPipeline::explain`].
+    pub async fn explain(
+        &self,
+        query: &str,
+        params: &RetrievalParams,
+    ) -> Result<(SearchExplanation, Vec<SearchResult>), RagError> {
+        if params.mode != RetrievalMode::Standard {
+            return Err(RagError::InvalidRequest(
+                "Search explanations are only available for the standard retrieval mode".to_owned(),
+            ));
+        }
+
+        let candidates = self.reranker_service.candidate_limit(params.top_k);
+        let filter = params.filter.as_deref();
+        let (explained, keyword, semantic) = futures::try_join!(
+            self.meilisearch_service.hybrid_search_explained(
+                query,
+                candidates,
+                params.semantic_ratio,
+                filter
+            ),
+            self.meilisearch_service
+                .hybrid_search(query, candidates, 0.0, filter),
+            self.meilisearch_service
+                .hybrid_search(query, candidates, 1.0, filter),
+        )?;
+        let score_in = |results: &[SearchResult], id: &str| {
+            results
+                .iter()
+                .find(|result| result.id == id)
+                .and_then(|result| result.ranking_score)
+        };
+
+        let (results, details): (Vec<_>, Vec<_>) = explained.into_iter().unzip();
+        let mut boosted = results.clone();
+        boost_language(&mut boosted, params.boost_language);
+
+        // Rerank without limit to tell results below the threshold from results cut by `top_k`
+        let reranked = self
+            .reranker_service
+            .rerank(query, boosted.clone(), boosted.len())
+            .await;
+        let top_results: Vec<SearchResult> = reranked.iter().take(params.top_k).cloned().collect();
+        let findings: HashMap<String, InjectionFinding> = top_results
+            .iter()
+            .filter_map(|result| {
+                let finding = self.screener.check(&result.content)?;
+                Some((result.id.clone(), finding))
+            })
+            .collect();
+        let final_results = self.screener.screen(top_results);
+        let position_in = |results: &[SearchResult], id: &str| {
+            results
+                .iter()
+                .position(|result| result.id == id)
+                .map(|index| index + 1)
+        };
+
+        let hits = results
+            .into_iter()
+            .zip(details)
+            .enumerate()
+            .map(|(index, (result, details))| {
+                let keyword_score = score_in(&keyword, &result.id);
+                let semantic_score = score_in(&semantic, &result.id);
+                let rerank_position = position_in(&reranked, &result.id);
+                let final_position = position_in(&final_results, &result.id);
+
+                HitExplanation {
+                    search_position: index + 1,
+                    ranking_score: result.ranking_score,
+                    keyword_contribution: keyword_score
+                        .map(|score| score * (1.0 - params.semantic_ratio as f64)),
+                    semantic_contribution: semantic_score
+                        .map(|score| score * params.semantic_ratio as f64),
+                    keyword_score,
+                    semantic_score,
+                    details,
+                    language_boosted: params.boost_language.is_some()
+                        && result.metadata.language == params.boost_language,
+                    boosted_position: position_in(&boosted, &result.id).unwrap_or(index + 1),
+                    rerank_score: rerank_position
+                        .and_then(|position| reranked[position - 1].rerank_score),
+                    rerank_position: rerank_position.filter(|_| self.reranker_service.is_enabled()),
+                    final_position,
+                    screening: findings.get(&result.id).map(|finding| HitScreening {
+                        action: match self.screener.action() {
+                            ScreeningAction::Quarantine => ScreeningOutcome::Quarantined,
+                            ScreeningAction::Neutralize => ScreeningOutcome::Neutralized,
+                            ScreeningAction::Off => ScreeningOutcome::Logged,
+                        },
+                        score: finding.score,
+                        rules: finding.rules.clone(),
+                    }),
+                    outcome: match (rerank_position, final_position) {
+                        (_, Some(_)) => HitOutcome::Kept,
+                        (None, None) => HitOutcome::BelowRerankThreshold,
+                        (Some(position), None) if position <= params.top_k => {
+                            HitOutcome::Quarantined
+                        }
+                        (Some(_), None) => HitOutcome::CutByTopK,
+                    },
+                    in_context: false,
+                    id: result.id,
+                    title: result.title,
+                    document_id: result.document_id,
+                    chunk_index: result.chunk_index,
+                }
+            })
+            .collect();
+
+        let explanation = SearchExplanation {
+            query: query.to_owned(),
+            semantic_ratio: params.semantic_ratio,
+            top_k: params.top_k,
+            candidates,
+            reranker_enabled: self.reranker_service.is_enabled(),
+            relevance_threshold: 0.0,
+            top_score: final_results
+                .iter()
+                .filter_map(|result| result.ranking_score)
+                .reduce(f64::max),
+            abstained: false,
+            contributions_approximate: true,
+            hits,
+        };
+
+        Ok((explanation, final_results))
+    }
+}
+
+/// Reorders a Meilisearch result list by ranking score with [`LANGUAGE_BOOST`]
+/// added for chunks in `language`. Chunks without a language are not boosted.
+fn boost_language(results: &mut [SearchResult], language: Option<Language>) {
+    let Some(language) = language else {
+        return;
+    };
+
+    let boosted_score = |result: &SearchResult| {
+        let boost = if result.metadata.language == Some(language) {
+            LANGUAGE_BOOST
+        } else {
+            0.0
+        };
+        result.ranking_score.unwrap_or_default() + boost
+    };
+    results.sort_by(|a, b| boosted_score(b).total_cmp(&boosted_score(a)));
+}
+
+/// Merges ranked result lists into a single deduplicated list ordered by
+/// reciprocal rank fusion score `sum(1 / (k + rank))`.
+///
+/// A result found by several queries keeps its best Meilisearch ranking score,
+/// which the relevance threshold is checked against. Ties keep the order in
+/// which the results were first seen, earlier lists first.
+fn reciprocal_rank_fusion(result_lists: Vec<Vec<SearchResult>>, limit: usize) -> Vec<SearchResult> {
+    let mut fused: Vec<(f64, SearchResult)> = Vec::new();
+    let mut positions: HashMap<String, usize> = HashMap::new();
+
+    for results in result_lists {
+        for (rank, result) in results.into_iter().enumerate() {
+            let score = 1.0 / (RRF_K + rank as f64 + 1.0);
+            match positions.get(&result.id) {
+                Some(&position) => {
+                    let (total, best) = &mut fused[position];
+                    *total += score;
+                    best.ranking_score = match (best.ranking_score, result.ranking_score) {
+                        (Some(a), Some(b)) => Some(a.max(b)),
+                        (a, b) => a.or(b),
+                    };
+                }
+                None => {
+                    positions.insert(result.id.to_owned(), fused.len());
+                    fused.push((score, result));
+                }
+            }
         }
+    }
+
+    // Stable, so ties stay in first-seen order
+    fused.sort_by(|a, b| b.0.total_cmp(&a.0));
+    fused.truncate(limit);
+
+    fused.into_iter().map(|(_, result)| result).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::DocumentMetadata;
+
+    fn result(id: &str, ranking_score: f64) -> SearchResult {
+        SearchResult {
+            id: id.to_owned(),
+            title: String::new(),
+            content: String::new(),
+            metadata: DocumentMetadata::default(),
+            source_file: None,
+            document_id: None,
+            chunk_index: None,
+            rerank_score: None,
+            ranking_score: Some(ranking_score),
+            merged_chunk_ids: Vec::new(),
+        }
+    }
+
+    fn ids(results: &[SearchResult]) -> Vec<&str> {
+        results.iter().map(|result| result.id.as_str()).collect()
+    }
+
+    #[test]
+    fn rrf_ranks_results_found_by_several_queries_first() {
+        let fused = reciprocal_rank_fusion(
+            vec![
+                vec![result("a", 0.9), result("b", 0.8), result("c", 0.7)],
+                vec![result("c", 0.6), result("d", 0.5)],
+                vec![result("c", 0.4), result("b", 0.3)],
+            ],
+            10,
+        );
+
+        // c: 1/63 + 1/61 + 1/61, b: 1/62 + 1/62, a: 1/61, d: 1/62
+        assert_eq!(ids(&fused), ["c", "b", "a", "d"]);
+    }
+
+    #[test]
+    fn rrf_keeps_the_best_ranking_score_across_variants() {
+        let fused = reciprocal_rank_fusion(
+            vec![
+                vec![result("x", 0.9), result("a", 0.3)],
+                vec![result("a", 0.8)],
+                vec![result("a", 0.5)],
+            ],
+            10,
+        );
+
+        let a = fused.iter().find(|result| result.id == "a").unwrap();
+        assert_eq!(a.ranking_score, Some(0.8));
+    }
+
+    #[test]
+    fn rrf_breaks_ties_by_first_seen_order_and_applies_the_limit() {
+        let fused = reciprocal_rank_fusion(
+            vec![
+                vec![result("a", 0.9), result("b", 0.8)],
+                vec![result("c", 0.7), result("d", 0.6)],
+            ],
+            3,
+        );
 
-        context_parts.join("")
+        assert_eq!(ids(&fused), ["a", "c", "b"]);
     }
 }
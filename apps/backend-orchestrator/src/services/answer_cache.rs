@@ -0,0 +1,246 @@
+use crate::models::{Citation, GenerationParams, Language, RetrievalMode, SearchResult};
+use std::env;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::RwLock;
+
+/// Settings a cached answer depends on besides the query. An entry is only
+/// returned for a turn with exactly the same scope.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CacheScope {
+    pub retrieval_mode: RetrievalMode,
+    pub top_k: usize,
+    pub semantic_ratio: f32,
+    pub filter: Option<String>,
+    pub neighbor_chunks: usize,
+    pub language: Language,
+    pub prompt_template: Option<String>,
+    pub generation: GenerationParams,
+}
+
+/// An answer returned from the cache together with the sources it was generated from.
+#[derive(Debug, Clone)]
+pub struct CachedAnswer {
+    pub query: String,
+    pub answer: String,
+    pub sources: Vec<SearchResult>,
+    pub citations: Vec<Citation>,
+    /// Follow-up questions suggested with the answer, replayed on a hit
+    pub follow_ups: Option<Vec<String>>,
+}
+
+struct CacheEntry {
+    embedding: Vec<f32>,
+    scope: CacheScope,
+    answer: CachedAnswer,
+}
+
+/// In-memory cache of generated answers keyed by query embedding.
+///
+/// A query hits the cache when its cosine similarity to a cached query is at
+/// least `ANSWER_CACHE_SIMILARITY` (default 0.95). The cache holds up to
+/// `ANSWER_CACHE_MAX_ENTRIES` answers (default 1000, 0 disables it) and drops
+/// the oldest entry when full.
+pub struct AnswerCache {
+    entries: RwLock<Vec<CacheEntry>>,
+    similarity_threshold: f32,
+    max_entries: usize,
+    /// Incremented by every invalidation, see [`AnswerCache::epoch`]
+    epoch: AtomicU64,
+}
+
+impl AnswerCache {
+    pub fn new() -> Self {
+        let similarity_threshold = env::var("ANSWER_CACHE_SIMILARITY")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(0.95);
+        let max_entries = env::var("ANSWER_CACHE_MAX_ENTRIES")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(1000);
+
+        Self {
+            entries: RwLock::new(Vec::new()),
+            similarity_threshold,
+            max_entries,
+            epoch: AtomicU64::new(0),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.max_entries > 0
+    }
+
+    /// Returns the cached answer of the most similar query above the threshold.
+    pub async fn get(&self, embedding: &[f32], scope: &CacheScope) -> Option<CachedAnswer> {
+        let entries = self.entries.read().await;
+
+        entries
+            .iter()
+            .filter(|entry| entry.scope == *scope)
+            .map(|entry| (cosine_similarity(&entry.embedding, embedding), entry))
+            .filter(|(similarity, _)| *similarity >= self.similarity_threshold)
+            .max_by(|(a, _), (b, _)| a.total_cmp(b))
+            .map(|(similarity, entry)| {
+                tracing::info!(
+                    "Answer cache hit for '{}' (similarity {:.3})",
+                    entry.answer.query,
+                    similarity
+                );
+                entry.answer.clone()
+            })
+    }
+
+    /// Taken before retrieval and passed to [`AnswerCache::insert`], so an
+    /// answer generated while its documents were replaced or deleted is not cached.
+    pub fn epoch(&self) -> u64 {
+        self.epoch.load(Ordering::SeqCst)
+    }
+
+    pub async fn insert(
+        &self,
+        epoch: u64,
+        embedding: Vec<f32>,
+        scope: CacheScope,
+        answer: CachedAnswer,
+    ) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        let mut entries = self.entries.write().await;
+        if self.epoch() != epoch {
+            tracing::info!(
+                "Answer for '{}' not cached, documents changed during generation",
+                answer.query
+            );
+            return;
+        }
+        if entries.len() >= self.max_entries {
+            entries.remove(0);
+        }
+        entries.push(CacheEntry {
+            embedding,
+            scope,
+            answer,
+        });
+    }
+
+    /// Drops all answers generated from a chunk of one of the given documents.
+    pub async fn invalidate_documents(&self, document_ids: &[&str]) {
+        let mut entries = self.entries.write().await;
+        self.epoch.fetch_add(1, Ordering::SeqCst);
+        let before = entries.len();
+
+        entries.retain(|entry| {
+            !entry.answer.sources.iter().any(|source| {
+                let document_id = source.document_id.as_deref().unwrap_or(&source.id);
+                document_ids.contains(&document_id)
+            })
+        });
+
+        let removed = before - entries.len();
+        if removed > 0 {
+            tracing::info!(
+                "Invalidated {} cached answers for documents {:?}",
+                removed,
+                document_ids
+            );
+        }
+    }
+}
+
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() {
+        return 0.0;
+    }
+
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+
+    dot / (norm_a * norm_b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scope() -> CacheScope {
+        CacheScope {
+            retrieval_mode: RetrievalMode::Standard,
+            top_k: 5,
+            semantic_ratio: 0.5,
+            filter: None,
+            neighbor_chunks: 0,
+            language: Language::En,
+            prompt_template: None,
+            generation: GenerationParams {
+                model: "mock".to_owned(),
+                temperature: 0.7,
+                top_p: None,
+                max_tokens: 512,
+                stop: Vec::new(),
+                seed: None,
+            },
+        }
+    }
+
+    fn answer() -> CachedAnswer {
+        CachedAnswer {
+            query: "question".to_owned(),
+            answer: "answer".to_owned(),
+            sources: Vec::new(),
+            citations: Vec::new(),
+            follow_ups: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn hits_only_with_the_same_retrieval_settings() {
+        let cache = AnswerCache::new();
+        cache
+            .insert(cache.epoch(), vec![1.0, 0.0], scope(), answer())
+            .await;
+
+        assert!(cache.get(&[1.0, 0.0], &scope()).await.is_some());
+        for other in [
+            CacheScope {
+                retrieval_mode: RetrievalMode::Hyde,
+                ..scope()
+            },
+            CacheScope {
+                top_k: 10,
+                ..scope()
+            },
+            CacheScope {
+                semantic_ratio: 0.9,
+                ..scope()
+            },
+            CacheScope {
+                neighbor_chunks: 2,
+                ..scope()
+            },
+        ] {
+            assert!(
+                cache.get(&[1.0, 0.0], &other).await.is_none(),
+                "{:?}",
+                other
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn skips_insert_after_invalidation_during_generation() {
+        let cache = AnswerCache::new();
+        let epoch = cache.epoch();
+
+        cache.invalidate_documents(&["other-document"]).await;
+        cache.insert(epoch, vec![1.0, 0.0], scope(), answer()).await;
+
+        assert!(cache.get(&[1.0, 0.0], &scope()).await.is_none());
+    }
+}
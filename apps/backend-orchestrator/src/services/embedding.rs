@@ -0,0 +1,62 @@
+use crate::error::{RagError, Result};
+use serde::{Deserialize, Serialize};
+use std::env;
+
+/// Client for the Ollama embedding API, using the same model as the
+/// Meilisearch `default` embedder so that query vectors match indexed vectors.
+pub struct EmbeddingService {
+    client: reqwest::Client,
+    api_url: String,
+    model: String,
+}
+
+#[derive(Serialize)]
+struct EmbedRequest<'a> {
+    model: &'a str,
+    input: Vec<&'a str>,
+}
+
+#[derive(Deserialize)]
+struct EmbedResponse {
+    embeddings: Vec<Vec<f32>>,
+}
+
+impl EmbeddingService {
+    pub fn new() -> Self {
+        let api_url =
+            env::var("EMBEDDING_API_URL").unwrap_or_else(|_| "http://localhost:11434".to_string());
+        let model =
+            env::var("EMBEDDING_MODEL").unwrap_or_else(|_| "embeddinggemma:300m".to_string());
+
+        Self::with_url(api_url, model)
+    }
+
+    pub fn with_url(api_url: String, model: String) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            api_url,
+            model,
+        }
+    }
+
+    pub async fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        let response = self
+            .client
+            .post(format!("{}/api/embed", self.api_url.trim_end_matches('/')))
+            .json(&EmbedRequest {
+                model: &self.model,
+                input: vec![text],
+            })
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<EmbedResponse>()
+            .await?;
+
+        response
+            .embeddings
+            .into_iter()
+            .next()
+            .ok_or_else(|| RagError::EmbeddingFailed("No embedding in response".to_string()))
+    }
+}
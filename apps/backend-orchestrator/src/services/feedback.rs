@@ -0,0 +1,531 @@
+use crate::error::{RagError, Result};
+use crate::models::{
+    ChatStreamEvent, Feedback, FeedbackRating, FeedbackRequest, FeedbackStats, SourceFeedbackCount,
+};
+use futures::Stream;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{Mutex, RwLock};
+
+/// Number of answers kept so feedback can refer to them.
+const MAX_RECENT_ANSWERS: usize = 10_000;
+
+/// Number of entries in the source rankings of [`FeedbackStats`].
+const MAX_RANKED_SOURCES: usize = 20;
+
+/// What feedback on an answer is linked to, captured while the answer streams.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct AnswerRecord {
+    request_id: String,
+    query: String,
+    chunk_ids: Vec<String>,
+    answer: String,
+}
+
+/// Recently completed answers by request id, oldest first.
+#[derive(Default)]
+struct RecentAnswers {
+    records: HashMap<String, AnswerRecord>,
+    order: VecDeque<String>,
+}
+
+/// Answer feedback, appended as JSON lines to `FEEDBACK_PATH`
+/// (default `data/feedback.jsonl`) and loaded from there on startup.
+///
+/// The answers feedback can refer to are appended to `FEEDBACK_ANSWERS_PATH`
+/// (default `data/answers.jsonl`), so feedback on an answer given before a
+/// restart is still accepted. Only the latest answers are kept.
+pub struct FeedbackStore {
+    path: PathBuf,
+    answers_path: PathBuf,
+    feedback: RwLock<Vec<Feedback>>,
+    recent: Mutex<RecentAnswers>,
+}
+
+impl FeedbackStore {
+    pub fn new() -> Result<Self> {
+        let path = PathBuf::from(
+            env::var("FEEDBACK_PATH").unwrap_or_else(|_| "data/feedback.jsonl".to_string()),
+        );
+        let answers_path = PathBuf::from(
+            env::var("FEEDBACK_ANSWERS_PATH").unwrap_or_else(|_| "data/answers.jsonl".to_string()),
+        );
+
+        Self::open(path, answers_path)
+    }
+
+    pub fn open(path: PathBuf, answers_path: PathBuf) -> Result<Self> {
+        let feedback: Vec<Feedback> = read_lines(&path)?;
+        tracing::info!(
+            "Loaded {} feedback entries from {}",
+            feedback.len(),
+            path.display()
+        );
+
+        let answers: Vec<AnswerRecord> = read_lines(&answers_path)?;
+        let mut recent = RecentAnswers::default();
+        let loaded = answers.len();
+        for answer in answers {
+            recent.insert(answer);
+        }
+        // Compacts the file, it is only appended to while running
+        if loaded > recent.order.len() {
+            let mut lines = String::new();
+            for request_id in &recent.order {
+                lines.push_str(&serde_json::to_string(&recent.records[request_id])?);
+                lines.push('\n');
+            }
+            std::fs::write(&answers_path, lines)?;
+        }
+        tracing::info!(
+            "Loaded {} answers from {}",
+            recent.order.len(),
+            answers_path.display()
+        );
+
+        Ok(Self {
+            path,
+            answers_path,
+            feedback: RwLock::new(feedback),
+            recent: Mutex::new(recent),
+        })
+    }
+
+    /// Passes the events of a chat turn through and remembers query, chunk ids
+    /// and answer under `request_id` once the turn is done.
+    pub fn record(
+        self: Arc<Self>,
+        request_id: String,
+        events: impl Stream<Item = ChatStreamEvent>,
+    ) -> impl Stream<Item = ChatStreamEvent> {
+        async_stream::stream! {
+            let mut record = AnswerRecord {
+                request_id: request_id.clone(),
+                ..AnswerRecord::default()
+            };
+
+            for await event in events {
+                match &event {
+                    ChatStreamEvent::Query { query } => record.query = query.clone(),
+                    ChatStreamEvent::Sources { sources } => {
+                        record.chunk_ids = sources.iter().map(|source| source.id.clone()).collect();
+                    }
+                    ChatStreamEvent::ToolResult { sources, .. } => {
+                        record.chunk_ids.extend(sources.iter().map(|source| source.id.clone()));
+                    }
+                    ChatStreamEvent::Token { content } => record.answer.push_str(content),
+                    ChatStreamEvent::Done { .. } => self.remember(record.clone()).await,
+                    _ => {}
+                }
+                yield event;
+            }
+        }
+    }
+
+    async fn remember(&self, record: AnswerRecord) {
+        // Hold the lock while appending so lines of concurrent answers don't interleave
+        let mut recent = self.recent.lock().await;
+        if let Err(err) = append_line(&self.answers_path, &record).await {
+            tracing::warn!(
+                "Failed to store answer {} in {}: {}",
+                record.request_id,
+                self.answers_path.display(),
+                err
+            );
+        }
+        recent.insert(record);
+    }
+
+    /// Stores feedback for a recent answer. Fails with [`RagError::NotFound`] if
+    /// the request id is unknown and [`RagError::InvalidRequest`] if the wrong
+    /// source was not part of the answer.
+    pub async fn submit(&self, request: FeedbackRequest) -> Result<Feedback> {
+        let record = self
+            .recent
+            .lock()
+            .await
+            .records
+            .get(&request.request_id)
+            .cloned()
+            .ok_or_else(|| {
+                RagError::NotFound(format!(
+                    "Unknown or expired request id '{}'",
+                    request.request_id
+                ))
+            })?;
+
+        if let Some(source_id) = &request.wrong_source_id
+            && !record.chunk_ids.contains(source_id)
+        {
+            return Err(RagError::InvalidRequest(format!(
+                "Source '{}' was not part of the answer",
+                source_id
+            )));
+        }
+
+        let feedback = Feedback {
+            id: uuid::Uuid::now_v7().to_string(),
+            request_id: request.request_id,
+            rating: request.rating,
+            comment: request
+                .comment
+                .map(|comment| comment.trim().to_owned())
+                .filter(|comment| !comment.is_empty()),
+            wrong_source_id: request.wrong_source_id,
+            query: record.query,
+            chunk_ids: record.chunk_ids,
+            answer: record.answer,
+            created_at: chrono::Utc::now(),
+        };
+
+        // Hold the lock while appending so lines of concurrent submissions don't interleave
+        let mut stored = self.feedback.write().await;
+        append_line(&self.path, &feedback).await?;
+        stored.push(feedback.clone());
+
+        tracing::info!(
+            "Feedback {:?} for request {}",
+            feedback.rating,
+            feedback.request_id
+        );
+        Ok(feedback)
+    }
+
+    /// All feedback as JSON lines, oldest first.
+    pub async fn export(&self) -> Result<String> {
+        let mut lines = String::new();
+        for feedback in self.feedback.read().await.iter() {
+            lines.push_str(&serde_json::to_string(feedback)?);
+            lines.push('\n');
+        }
+        Ok(lines)
+    }
+
+    pub async fn stats(&self) -> FeedbackStats {
+        let feedback = self.feedback.read().await;
+
+        let mut wrong_sources: HashMap<&str, usize> = HashMap::new();
+        let mut down_rated_chunks: HashMap<&str, usize> = HashMap::new();
+        for entry in feedback.iter() {
+            if let Some(source_id) = &entry.wrong_source_id {
+                *wrong_sources.entry(source_id).or_default() += 1;
+            }
+            if entry.rating == FeedbackRating::Down {
+                for chunk_id in &entry.chunk_ids {
+                    *down_rated_chunks.entry(chunk_id).or_default() += 1;
+                }
+            }
+        }
+
+        let up = feedback
+            .iter()
+            .filter(|entry| entry.rating == FeedbackRating::Up)
+            .count();
+
+        FeedbackStats {
+            total: feedback.len(),
+            up,
+            down: feedback.len() - up,
+            with_comment: feedback
+                .iter()
+                .filter(|entry| entry.comment.is_some())
+                .count(),
+            wrong_sources: ranked(wrong_sources),
+            down_rated_chunks: ranked(down_rated_chunks),
+        }
+    }
+}
+
+impl RecentAnswers {
+    fn insert(&mut self, record: AnswerRecord) {
+        let request_id = record.request_id.clone();
+        if self.records.insert(request_id.clone(), record).is_none() {
+            self.order.push_back(request_id);
+        }
+        while self.order.len() > MAX_RECENT_ANSWERS {
+            if let Some(oldest) = self.order.pop_front() {
+                self.records.remove(&oldest);
+            }
+        }
+    }
+}
+
+/// Reads a JSON lines file, skipping invalid lines. A missing file has no lines.
+fn read_lines<T: DeserializeOwned>(path: &Path) -> Result<Vec<T>> {
+    let mut entries = Vec::new();
+    if !path.is_file() {
+        return Ok(entries);
+    }
+
+    for (number, line) in std::fs::read_to_string(path)?.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(line) {
+            Ok(entry) => entries.push(entry),
+            Err(err) => tracing::warn!(
+                "Skipping invalid line {} in {}: {}",
+                number + 1,
+                path.display(),
+                err
+            ),
+        }
+    }
+
+    Ok(entries)
+}
+
+async fn append_line(path: &Path, value: &impl Serialize) -> Result<()> {
+    if let Some(parent) = path.parent()
+        && !parent.as_os_str().is_empty()
+    {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+
+    let mut line = serde_json::to_string(value)?;
+    line.push('\n');
+
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?;
+    file.write_all(line.as_bytes()).await?;
+    file.flush().await?;
+
+    Ok(())
+}
+
+fn ranked(counts: HashMap<&str, usize>) -> Vec<SourceFeedbackCount> {
+    let mut ranked: Vec<SourceFeedbackCount> = counts
+        .into_iter()
+        .map(|(source_id, count)| SourceFeedbackCount {
+            source_id: source_id.to_owned(),
+            count,
+        })
+        .collect();
+    ranked.sort_by(|a, b| b.count.cmp(&a.count).then(a.source_id.cmp(&b.source_id)));
+    ranked.truncate(MAX_RANKED_SOURCES);
+    ranked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+
+    struct TempStore {
+        path: PathBuf,
+        answers_path: PathBuf,
+    }
+
+    impl TempStore {
+        fn new(name: &str) -> Self {
+            let dir = env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            Self {
+                path: dir.join("feedback.jsonl"),
+                answers_path: dir.join("answers.jsonl"),
+            }
+        }
+
+        fn open(&self) -> Arc<FeedbackStore> {
+            Arc::new(FeedbackStore::open(self.path.clone(), self.answers_path.clone()).unwrap())
+        }
+    }
+
+    impl Drop for TempStore {
+        fn drop(&mut self) {
+            if let Some(dir) = self.path.parent() {
+                let _ = std::fs::remove_dir_all(dir);
+            }
+        }
+    }
+
+    async fn answer(store: &Arc<FeedbackStore>, request_id: &str, chunk_ids: &[&str]) {
+        let sources = chunk_ids
+            .iter()
+            .map(|id| crate::models::SearchResult {
+                id: id.to_string(),
+                title: String::new(),
+                content: String::new(),
+                metadata: Default::default(),
+                source_file: None,
+                document_id: None,
+                chunk_index: None,
+                rerank_score: None,
+                ranking_score: None,
+                merged_chunk_ids: Vec::new(),
+            })
+            .collect();
+        let events = futures::stream::iter([
+            ChatStreamEvent::Query {
+                query: format!("question {}", request_id),
+            },
+            ChatStreamEvent::Sources { sources },
+            ChatStreamEvent::Token {
+                content: "The ".to_owned(),
+            },
+            ChatStreamEvent::Token {
+                content: "answer".to_owned(),
+            },
+            ChatStreamEvent::Done {
+                session_id: None,
+                citations: Vec::new(),
+                generation: None,
+                cancelled: false,
+                truncated: false,
+                cached: false,
+            },
+        ]);
+
+        let passed: Vec<_> = store
+            .clone()
+            .record(request_id.to_owned(), events)
+            .collect()
+            .await;
+        assert_eq!(passed.len(), 5);
+    }
+
+    fn rate(
+        request_id: &str,
+        rating: FeedbackRating,
+        wrong_source_id: Option<&str>,
+    ) -> FeedbackRequest {
+        FeedbackRequest {
+            request_id: request_id.to_owned(),
+            rating,
+            comment: None,
+            wrong_source_id: wrong_source_id.map(str::to_owned),
+        }
+    }
+
+    #[tokio::test]
+    async fn links_feedback_to_the_recorded_answer() {
+        let temp = TempStore::new("feedback-submit");
+        let store = temp.open();
+        answer(&store, "req-1", &["a", "b"]).await;
+
+        let mut request = rate("req-1", FeedbackRating::Down, Some("b"));
+        request.comment = Some("  outdated  ".to_owned());
+        let feedback = store.submit(request).await.unwrap();
+
+        assert_eq!(feedback.query, "question req-1");
+        assert_eq!(feedback.chunk_ids, ["a", "b"]);
+        assert_eq!(feedback.answer, "The answer");
+        assert_eq!(feedback.comment.as_deref(), Some("outdated"));
+    }
+
+    #[tokio::test]
+    async fn rejects_unknown_answers_and_foreign_sources() {
+        let temp = TempStore::new("feedback-reject");
+        let store = temp.open();
+        answer(&store, "req-1", &["a"]).await;
+
+        assert!(matches!(
+            store
+                .submit(rate("unknown", FeedbackRating::Up, None))
+                .await,
+            Err(RagError::NotFound(_))
+        ));
+        assert!(matches!(
+            store
+                .submit(rate("req-1", FeedbackRating::Down, Some("other")))
+                .await,
+            Err(RagError::InvalidRequest(_))
+        ));
+        assert!(store.export().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn keeps_answers_and_feedback_across_restarts() {
+        let temp = TempStore::new("feedback-restart");
+        let store = temp.open();
+        answer(&store, "before", &["a"]).await;
+        store
+            .submit(rate("before", FeedbackRating::Up, None))
+            .await
+            .unwrap();
+        drop(store);
+
+        let store = temp.open();
+        let feedback = store
+            .submit(rate("before", FeedbackRating::Down, Some("a")))
+            .await
+            .unwrap();
+
+        assert_eq!(feedback.query, "question before");
+        assert_eq!(store.stats().await.total, 2);
+    }
+
+    #[tokio::test]
+    async fn exports_feedback_as_json_lines() {
+        let temp = TempStore::new("feedback-export");
+        let store = temp.open();
+        answer(&store, "req-1", &["a"]).await;
+        answer(&store, "req-2", &["b"]).await;
+        for request_id in ["req-1", "req-2"] {
+            store
+                .submit(rate(request_id, FeedbackRating::Up, None))
+                .await
+                .unwrap();
+        }
+
+        let export = store.export().await.unwrap();
+        let request_ids: Vec<String> = export
+            .lines()
+            .map(|line| serde_json::from_str::<Feedback>(line).unwrap().request_id)
+            .collect();
+
+        assert_eq!(request_ids, ["req-1", "req-2"]);
+        assert_eq!(export, std::fs::read_to_string(&temp.path).unwrap());
+    }
+
+    #[tokio::test]
+    async fn ranks_reported_sources_and_down_rated_chunks() {
+        let temp = TempStore::new("feedback-stats");
+        let store = temp.open();
+        answer(&store, "req-1", &["a", "b"]).await;
+        answer(&store, "req-2", &["b", "c"]).await;
+        answer(&store, "req-3", &["c"]).await;
+        store
+            .submit(rate("req-1", FeedbackRating::Down, Some("b")))
+            .await
+            .unwrap();
+        store
+            .submit(rate("req-2", FeedbackRating::Down, Some("b")))
+            .await
+            .unwrap();
+        let mut request = rate("req-3", FeedbackRating::Up, Some("c"));
+        request.comment = Some("fine".to_owned());
+        store.submit(request).await.unwrap();
+
+        let stats = store.stats().await;
+        let counts = |ranking: &[SourceFeedbackCount]| -> Vec<(String, usize)> {
+            ranking
+                .iter()
+                .map(|entry| (entry.source_id.clone(), entry.count))
+                .collect()
+        };
+
+        assert_eq!((stats.total, stats.up, stats.down), (3, 1, 2));
+        assert_eq!(stats.with_comment, 1);
+        assert_eq!(
+            counts(&stats.wrong_sources),
+            [("b".to_owned(), 2), ("c".to_owned(), 1)]
+        );
+        assert_eq!(
+            counts(&stats.down_rated_chunks),
+            [
+                ("b".to_owned(), 2),
+                ("a".to_owned(), 1),
+                ("c".to_owned(), 1)
+            ]
+        );
+    }
+}
@@ -0,0 +1,204 @@
+use futures::Stream;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio_util::sync::CancellationToken;
+
+/// Tracks running chat generations so they can be cancelled, either explicitly
+/// by request id or implicitly when the client goes away.
+pub struct GenerationRegistry {
+    active: Mutex<HashMap<String, CancellationToken>>,
+    started: AtomicU64,
+    completed: AtomicU64,
+    cancelled_by_request: AtomicU64,
+    cancelled_by_disconnect: AtomicU64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GenerationMetrics {
+    pub active: usize,
+    pub started: u64,
+    pub completed: u64,
+    /// Cancelled via `POST /api/chat/{request_id}/cancel`
+    pub cancelled_by_request: u64,
+    /// Client disconnected before the response was complete
+    pub cancelled_by_disconnect: u64,
+}
+
+/// Registration of a running generation. Dropping the guard without calling
+/// [`GenerationGuard::finish`] counts as a client disconnect and cancels the token.
+pub struct GenerationGuard {
+    registry: Arc<GenerationRegistry>,
+    request_id: String,
+    token: CancellationToken,
+    finished: bool,
+}
+
+impl GenerationRegistry {
+    pub fn new() -> Self {
+        Self {
+            active: Mutex::new(HashMap::new()),
+            started: AtomicU64::new(0),
+            completed: AtomicU64::new(0),
+            cancelled_by_request: AtomicU64::new(0),
+            cancelled_by_disconnect: AtomicU64::new(0),
+        }
+    }
+
+    /// Registers a generation under a server-generated `request_id`, so other
+    /// clients can't guess it to cancel the generation.
+    pub fn register(self: &Arc<Self>, request_id: String) -> GenerationGuard {
+        let token = CancellationToken::new();
+
+        self.active
+            .lock()
+            .expect("generation registry lock poisoned")
+            .insert(request_id.clone(), token.clone());
+        self.started.fetch_add(1, Ordering::Relaxed);
+
+        GenerationGuard {
+            registry: self.clone(),
+            request_id,
+            token,
+            finished: false,
+        }
+    }
+
+    /// Cancels a running generation. Returns `false` if none is running under `request_id`.
+    pub fn cancel(&self, request_id: &str) -> bool {
+        let active = self
+            .active
+            .lock()
+            .expect("generation registry lock poisoned");
+        match active.get(request_id) {
+            Some(token) if !token.is_cancelled() => {
+                token.cancel();
+                self.cancelled_by_request.fetch_add(1, Ordering::Relaxed);
+                tracing::info!("Generation {} cancelled by request", request_id);
+                true
+            }
+            Some(_) => true,
+            None => false,
+        }
+    }
+
+    pub fn metrics(&self) -> GenerationMetrics {
+        GenerationMetrics {
+            active: self
+                .active
+                .lock()
+                .expect("generation registry lock poisoned")
+                .len(),
+            started: self.started.load(Ordering::Relaxed),
+            completed: self.completed.load(Ordering::Relaxed),
+            cancelled_by_request: self.cancelled_by_request.load(Ordering::Relaxed),
+            cancelled_by_disconnect: self.cancelled_by_disconnect.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl GenerationGuard {
+    pub fn token(&self) -> CancellationToken {
+        self.token.clone()
+    }
+
+    /// Marks the response as fully delivered to the client.
+    pub fn finish(mut self) {
+        self.finished = true;
+        if !self.token.is_cancelled() {
+            self.registry.completed.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Passes the events through and finishes the guard after the last one.
+    /// Dropping the stream before that counts as a client disconnect.
+    pub fn attach<S: Stream>(self, events: S) -> impl Stream<Item = S::Item> {
+        async_stream::stream! {
+            for await event in events {
+                yield event;
+            }
+            self.finish();
+        }
+    }
+}
+
+impl Drop for GenerationGuard {
+    fn drop(&mut self) {
+        self.registry
+            .active
+            .lock()
+            .expect("generation registry lock poisoned")
+            .remove(&self.request_id);
+
+        if !self.finished && !self.token.is_cancelled() {
+            self.token.cancel();
+            self.registry
+                .cancelled_by_disconnect
+                .fetch_add(1, Ordering::Relaxed);
+            tracing::info!(
+                "Client disconnected, generation {} cancelled",
+                self.request_id
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn dropping_the_stream_cancels_the_generation() {
+        let registry = Arc::new(GenerationRegistry::new());
+        let guard = registry.register("request".to_owned());
+        let token = guard.token();
+
+        let mut stream =
+            Box::pin(guard.attach(futures::stream::iter([1, 2]).chain(futures::stream::pending())));
+        assert_eq!(stream.next().await, Some(1));
+        assert!(!token.is_cancelled());
+        drop(stream);
+
+        let metrics = registry.metrics();
+        assert!(token.is_cancelled());
+        assert_eq!(metrics.active, 0);
+        assert_eq!(metrics.cancelled_by_disconnect, 1);
+        assert_eq!(metrics.completed, 0);
+    }
+
+    #[tokio::test]
+    async fn completes_when_the_stream_is_consumed() {
+        let registry = Arc::new(GenerationRegistry::new());
+        let guard = registry.register("request".to_owned());
+        let token = guard.token();
+
+        let events: Vec<_> = guard.attach(futures::stream::iter([1, 2])).collect().await;
+
+        let metrics = registry.metrics();
+        assert_eq!(events, [1, 2]);
+        assert!(!token.is_cancelled());
+        assert_eq!(
+            (metrics.active, metrics.started, metrics.completed),
+            (0, 1, 1)
+        );
+        assert_eq!(metrics.cancelled_by_disconnect, 0);
+    }
+
+    #[tokio::test]
+    async fn cancels_by_request_id() {
+        let registry = Arc::new(GenerationRegistry::new());
+        let guard = registry.register("request".to_owned());
+
+        assert!(!registry.cancel("other"));
+        assert!(registry.cancel("request"));
+        assert!(guard.token().is_cancelled());
+        drop(guard);
+
+        let metrics = registry.metrics();
+        assert_eq!(metrics.cancelled_by_request, 1);
+        assert_eq!(metrics.cancelled_by_disconnect, 0);
+        assert!(!registry.cancel("request"));
+    }
+}
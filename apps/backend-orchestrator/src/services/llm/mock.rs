@@ -0,0 +1,142 @@
+use super::{
+    AssistantTurn, LlmProvider, LlmRequest, StreamToken, TokenStream, ToolCall, ToolDefinition,
+};
+use crate::error::Result;
+use async_trait::async_trait;
+use futures::StreamExt;
+use serde::Deserialize;
+use std::env;
+use std::fs;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+#[derive(Deserialize)]
+struct ScriptedToolCall {
+    tool_call: ScriptedCall,
+}
+
+#[derive(Deserialize)]
+struct ScriptedCall {
+    name: String,
+    #[serde(default)]
+    arguments: serde_json::Value,
+}
+
+const DEFAULT_RESPONSE: &str = "This is a scripted answer from the mock LLM provider [Source 1].";
+
+/// Deterministic provider replaying a script of responses in call order,
+/// starting over at the end. Streams responses word by word, one word counting
+/// as one token of `max_tokens`.
+///
+/// With tools, a response of the form `{"tool_call": {"name": ..., "arguments": {...}}}`
+/// is returned as a call to that tool instead of as content. All requests are
+/// recorded, see [`MockProvider::requests`].
+pub struct MockProvider {
+    responses: Vec<String>,
+    calls: AtomicUsize,
+    requests: Arc<Mutex<Vec<LlmRequest>>>,
+}
+
+impl MockProvider {
+    pub fn new(responses: Vec<String>) -> Self {
+        let responses = if responses.is_empty() {
+            vec![DEFAULT_RESPONSE.to_owned()]
+        } else {
+            responses
+        };
+
+        Self {
+            responses,
+            calls: AtomicUsize::new(0),
+            requests: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// The requests received so far, in call order. The handle stays valid
+    /// after the provider was moved into an [`super::LlmService`].
+    pub fn requests(&self) -> Arc<Mutex<Vec<LlmRequest>>> {
+        self.requests.clone()
+    }
+
+    /// Reads the script from `LLM_MOCK_SCRIPT`, a file with one response per
+    /// section, sections separated by a line `---`.
+    pub fn from_env() -> Result<Self> {
+        let responses = match env::var("LLM_MOCK_SCRIPT") {
+            Ok(path) => fs::read_to_string(path)?
+                .split("\n---\n")
+                .map(|response| response.trim().to_owned())
+                .filter(|response| !response.is_empty())
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+
+        Ok(Self::new(responses))
+    }
+
+    fn next_response(&self, request: LlmRequest) -> String {
+        self.requests
+            .lock()
+            .expect("mock requests lock poisoned")
+            .push(request);
+        let call = self.calls.fetch_add(1, Ordering::Relaxed);
+        self.responses[call % self.responses.len()].to_owned()
+    }
+}
+
+#[async_trait]
+impl LlmProvider for MockProvider {
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+
+    fn default_model(&self) -> &'static str {
+        "mock"
+    }
+
+    async fn chat(&self, request: LlmRequest) -> Result<String> {
+        Ok(self.next_response(request))
+    }
+
+    async fn chat_stream(&self, request: LlmRequest) -> Result<TokenStream> {
+        let max_tokens = request.params.max_tokens as usize;
+        let response = self.next_response(request);
+        let words: Vec<&str> = response.split_inclusive(' ').collect();
+
+        let mut tokens: Vec<Result<StreamToken>> = words
+            .iter()
+            .take(max_tokens)
+            .map(|word| Ok(StreamToken::Text(word.to_string())))
+            .collect();
+        if words.len() > max_tokens {
+            tokens.push(Ok(StreamToken::Truncated));
+        }
+
+        Ok(futures::stream::iter(tokens).boxed())
+    }
+
+    async fn chat_with_tools(
+        &self,
+        request: LlmRequest,
+        _tools: &[ToolDefinition],
+    ) -> Result<AssistantTurn> {
+        let call = self.calls.load(Ordering::Relaxed);
+        let response = self.next_response(request);
+
+        Ok(match serde_json::from_str::<ScriptedToolCall>(&response) {
+            Ok(scripted) => AssistantTurn {
+                content: String::new(),
+                tool_calls: vec![ToolCall {
+                    id: format!("call_{}", call),
+                    name: scripted.tool_call.name,
+                    arguments: scripted.tool_call.arguments,
+                }],
+                truncated: false,
+            },
+            Err(_) => AssistantTurn {
+                content: response,
+                tool_calls: Vec::new(),
+                truncated: false,
+            },
+        })
+    }
+}
@@ -0,0 +1,368 @@
+pub mod mock;
+pub mod ollama;
+pub mod openai;
+
+use crate::error::{RagError, Result};
+use crate::models::{ChatMessage, ChatRole, GenerationOptions, GenerationParams};
+use async_trait::async_trait;
+use futures::stream::BoxStream;
+use serde::Serialize;
+use std::env;
+
+pub use mock::MockProvider;
+pub use ollama::OllamaProvider;
+pub use openai::OpenAiCompatibleProvider;
+
+/// Stream of generated text fragments.
+pub type TokenStream = BoxStream<'static, Result<StreamToken>>;
+
+/// An item of a [`TokenStream`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum StreamToken {
+    Text(String),
+    /// The answer was cut off at `max_tokens`, sent after the last text
+    Truncated,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LlmRole {
+    System,
+    User,
+    Assistant,
+    /// Result of a tool call, answering the assistant message that requested it
+    Tool,
+}
+
+#[derive(Debug, Clone)]
+pub struct LlmMessage {
+    pub role: LlmRole,
+    pub content: String,
+    /// Tool calls requested by an assistant message
+    pub tool_calls: Vec<ToolCall>,
+    /// Id of the call a `Tool` message answers
+    pub tool_call_id: Option<String>,
+}
+
+impl LlmMessage {
+    pub fn new(role: LlmRole, content: impl Into<String>) -> Self {
+        Self {
+            role,
+            content: content.into(),
+            tool_calls: Vec::new(),
+            tool_call_id: None,
+        }
+    }
+
+    pub fn tool_result(tool_call_id: &str, content: impl Into<String>) -> Self {
+        Self {
+            tool_call_id: Some(tool_call_id.to_owned()),
+            ..Self::new(LlmRole::Tool, content)
+        }
+    }
+}
+
+/// A function the model may call, described by a JSON schema.
+#[derive(Debug, Clone)]
+pub struct ToolDefinition {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub parameters: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ToolCall {
+    pub id: String,
+    pub name: String,
+    pub arguments: serde_json::Value,
+}
+
+/// Assistant reply to a request with tools: either tool calls or the final content.
+#[derive(Debug, Clone)]
+pub struct AssistantTurn {
+    pub content: String,
+    pub tool_calls: Vec<ToolCall>,
+    /// The content was cut off at `max_tokens`
+    pub truncated: bool,
+}
+
+impl AssistantTurn {
+    /// The turn as message for the next request of a tool loop.
+    pub fn into_message(self) -> LlmMessage {
+        LlmMessage {
+            tool_calls: self.tool_calls,
+            ..LlmMessage::new(LlmRole::Assistant, self.content)
+        }
+    }
+}
+
+/// A chat completion request, independent of the backend.
+#[derive(Debug, Clone)]
+pub struct LlmRequest {
+    pub messages: Vec<LlmMessage>,
+    pub params: GenerationParams,
+}
+
+/// Maximum number of stop sequences, as accepted by the OpenAI API.
+const MAX_STOP_SEQUENCES: usize = 4;
+
+/// Chat completion backend.
+#[async_trait]
+pub trait LlmProvider: Send + Sync {
+    /// Short name for logging, e.g. `ollama`
+    fn name(&self) -> &'static str;
+
+    /// Model used unless `LLM_MODEL` is set
+    fn default_model(&self) -> &'static str;
+
+    async fn chat(&self, request: LlmRequest) -> Result<String>;
+
+    async fn chat_stream(&self, request: LlmRequest) -> Result<TokenStream>;
+
+    /// Non-streaming chat where the model may answer with calls to `tools`.
+    async fn chat_with_tools(
+        &self,
+        request: LlmRequest,
+        tools: &[ToolDefinition],
+    ) -> Result<AssistantTurn>;
+}
+
+/// Chat completions for the RAG pipeline, delegating to the provider selected
+/// with `LLM_PROVIDER`: `openai` (default, llama.cpp/vLLM), `ollama` or `mock`.
+pub struct LlmService {
+    provider: Box<dyn LlmProvider>,
+    /// Used when a request does not override a parameter
+    defaults: GenerationParams,
+    /// Models a request may select, always including the default model
+    allowed_models: Vec<String>,
+    max_tokens_limit: u32,
+}
+
+impl LlmService {
+    pub fn new() -> Result<Self> {
+        let provider: Box<dyn LlmProvider> = match env::var("LLM_PROVIDER")
+            .unwrap_or_else(|_| "openai".to_string())
+            .as_str()
+        {
+            "openai" => Box::new(OpenAiCompatibleProvider::new()),
+            "ollama" => Box::new(OllamaProvider::new()),
+            "mock" => Box::new(MockProvider::from_env()?),
+            other => {
+                return Err(RagError::Config(format!(
+                    "Unknown LLM_PROVIDER '{}', expected openai, ollama or mock",
+                    other
+                )));
+            }
+        };
+
+        Ok(Self::with_provider(provider))
+    }
+
+    /// Uses the given provider instead of the one selected by `LLM_PROVIDER`,
+    /// e.g. a [`MockProvider`] with its own script in tests.
+    pub fn with_provider(provider: Box<dyn LlmProvider>) -> Self {
+        let defaults = GenerationParams {
+            model: env::var("LLM_MODEL").unwrap_or_else(|_| provider.default_model().to_string()),
+            temperature: env_or("LLM_TEMPERATURE", 0.7),
+            top_p: env::var("LLM_TOP_P")
+                .ok()
+                .and_then(|value| value.parse().ok()),
+            max_tokens: env_or("LLM_MAX_TOKENS", 2048),
+            stop: Vec::new(),
+            seed: None,
+        };
+        let mut allowed_models: Vec<String> = env::var("LLM_ALLOWED_MODELS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|model| model.trim().to_owned())
+            .filter(|model| !model.is_empty())
+            .collect();
+        if !allowed_models.contains(&defaults.model) {
+            allowed_models.push(defaults.model.to_owned());
+        }
+        let max_tokens_limit = env_or("LLM_MAX_TOKENS_LIMIT", defaults.max_tokens.max(4096));
+
+        tracing::info!(
+            "Using LLM provider '{}' with model '{}'",
+            provider.name(),
+            defaults.model
+        );
+
+        Self {
+            provider,
+            defaults,
+            allowed_models,
+            max_tokens_limit,
+        }
+    }
+
+    /// Applies the per-request overrides to the configured defaults and checks
+    /// them against the allowlist and limits.
+    pub fn resolve_params(&self, options: &GenerationOptions) -> Result<GenerationParams> {
+        let invalid = |message: String| Err(RagError::InvalidRequest(message));
+
+        if let Some(model) = &options.model
+            && !self.allowed_models.contains(model)
+        {
+            return invalid(format!(
+                "Model '{}' is not allowed. Allowed models: {}",
+                model,
+                self.allowed_models.join(", ")
+            ));
+        }
+        if let Some(temperature) = options.temperature
+            && !(0.0..=2.0).contains(&temperature)
+        {
+            return invalid("temperature must be between 0.0 and 2.0".to_string());
+        }
+        if let Some(top_p) = options.top_p
+            && !(0.0..=1.0).contains(&top_p)
+        {
+            return invalid("top_p must be between 0.0 and 1.0".to_string());
+        }
+        if let Some(max_tokens) = options.max_tokens
+            && !(1..=self.max_tokens_limit).contains(&max_tokens)
+        {
+            return invalid(format!(
+                "max_tokens must be between 1 and {}",
+                self.max_tokens_limit
+            ));
+        }
+        if let Some(stop) = &options.stop
+            && (stop.len() > MAX_STOP_SEQUENCES || stop.iter().any(String::is_empty))
+        {
+            return invalid(format!(
+                "stop must contain at most {} non-empty sequences",
+                MAX_STOP_SEQUENCES
+            ));
+        }
+
+        Ok(GenerationParams {
+            model: options
+                .model
+                .to_owned()
+                .unwrap_or_else(|| self.defaults.model.to_owned()),
+            temperature: options.temperature.unwrap_or(self.defaults.temperature),
+            top_p: options.top_p.or(self.defaults.top_p),
+            max_tokens: options.max_tokens.unwrap_or(self.defaults.max_tokens),
+            stop: options
+                .stop
+                .to_owned()
+                .unwrap_or_else(|| self.defaults.stop.to_owned()),
+            seed: options.seed.or(self.defaults.seed),
+        })
+    }
+
+    pub async fn generate_response_stream(
+        &self,
+        system_prompt: &str,
+        history: &[ChatMessage],
+        user_prompt: &str,
+        params: &GenerationParams,
+    ) -> Result<TokenStream> {
+        self.provider
+            .chat_stream(LlmRequest {
+                messages: Self::build_messages(system_prompt, history, user_prompt),
+                params: params.to_owned(),
+            })
+            .await
+    }
+
+    pub async fn generate_response(
+        &self,
+        system_prompt: &str,
+        history: &[ChatMessage],
+        user_prompt: &str,
+        params: &GenerationParams,
+    ) -> Result<String> {
+        self.provider
+            .chat(LlmRequest {
+                messages: Self::build_messages(system_prompt, history, user_prompt),
+                params: params.to_owned(),
+            })
+            .await
+    }
+
+    /// Streams the answer to an explicit message list, e.g. at the end of a tool loop.
+    pub async fn stream_messages(
+        &self,
+        messages: Vec<LlmMessage>,
+        params: &GenerationParams,
+    ) -> Result<TokenStream> {
+        self.provider
+            .chat_stream(LlmRequest {
+                messages,
+                params: params.to_owned(),
+            })
+            .await
+    }
+
+    pub async fn chat_with_tools(
+        &self,
+        messages: Vec<LlmMessage>,
+        tools: &[ToolDefinition],
+        params: &GenerationParams,
+    ) -> Result<AssistantTurn> {
+        self.provider
+            .chat_with_tools(
+                LlmRequest {
+                    messages,
+                    params: params.to_owned(),
+                },
+                tools,
+            )
+            .await
+    }
+
+    /// Runs a single non-streaming completion without retrieved context, used for
+    /// auxiliary tasks like query rewriting.
+    pub async fn complete(
+        &self,
+        system_prompt: &str,
+        prompt: &str,
+        temperature: f32,
+        max_tokens: u32,
+    ) -> Result<String> {
+        self.provider
+            .chat(LlmRequest {
+                messages: vec![
+                    LlmMessage::new(LlmRole::System, system_prompt),
+                    LlmMessage::new(LlmRole::User, prompt),
+                ],
+                params: GenerationParams {
+                    temperature,
+                    max_tokens,
+                    ..self.defaults.to_owned()
+                },
+            })
+            .await
+    }
+
+    /// Builds the chat messages: system prompt, previous turns of the session
+    /// and the rendered user prompt with the current question.
+    pub fn build_messages(
+        system_prompt: &str,
+        history: &[ChatMessage],
+        user_prompt: &str,
+    ) -> Vec<LlmMessage> {
+        let mut messages = vec![LlmMessage::new(LlmRole::System, system_prompt)];
+
+        for message in history {
+            let role = match message.role {
+                ChatRole::User => LlmRole::User,
+                ChatRole::Assistant => LlmRole::Assistant,
+            };
+            messages.push(LlmMessage::new(role, message.content.as_str()));
+        }
+
+        messages.push(LlmMessage::new(LlmRole::User, user_prompt));
+
+        messages
+    }
+}
+
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
@@ -0,0 +1,337 @@
+use super::{
+    AssistantTurn, LlmProvider, LlmRequest, LlmRole, StreamToken, TokenStream, ToolCall,
+    ToolDefinition,
+};
+use crate::error::{RagError, Result};
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::env;
+
+/// Provider for the native Ollama `/api/chat` endpoint.
+pub struct OllamaProvider {
+    client: reqwest::Client,
+    api_url: String,
+}
+
+#[derive(Serialize)]
+struct OllamaChatRequest<'a> {
+    model: &'a str,
+    messages: Vec<OllamaMessage<'a>>,
+    stream: bool,
+    options: OllamaOptions<'a>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    tools: Vec<OllamaTool<'a>>,
+}
+
+#[derive(Serialize)]
+struct OllamaMessage<'a> {
+    role: &'static str,
+    content: &'a str,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    tool_calls: Vec<OllamaToolCall>,
+}
+
+#[derive(Serialize)]
+struct OllamaTool<'a> {
+    r#type: &'static str,
+    function: OllamaFunction<'a>,
+}
+
+#[derive(Serialize)]
+struct OllamaFunction<'a> {
+    name: &'a str,
+    description: &'a str,
+    parameters: &'a serde_json::Value,
+}
+
+#[derive(Serialize, Deserialize)]
+struct OllamaToolCall {
+    function: OllamaFunctionCall,
+}
+
+#[derive(Serialize, Deserialize)]
+struct OllamaFunctionCall {
+    name: String,
+    #[serde(default)]
+    arguments: serde_json::Value,
+}
+
+#[derive(Serialize)]
+struct OllamaOptions<'a> {
+    temperature: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    num_predict: u32,
+    #[serde(skip_serializing_if = "<[String]>::is_empty")]
+    stop: &'a [String],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<i64>,
+}
+
+/// A response, or with `stream: true` one line of the NDJSON response stream.
+#[derive(Deserialize)]
+struct OllamaChatResponse {
+    #[serde(default)]
+    message: Option<OllamaResponseMessage>,
+    #[serde(default)]
+    error: Option<String>,
+    /// Set on the last response, `length` if cut off at `num_predict`
+    #[serde(default)]
+    done_reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct OllamaResponseMessage {
+    content: String,
+    #[serde(default)]
+    tool_calls: Vec<OllamaToolCall>,
+}
+
+impl OllamaChatResponse {
+    fn into_content(self) -> Result<String> {
+        match (self.error, self.message) {
+            (Some(error), _) => Err(RagError::LlmFailed(error)),
+            (None, Some(message)) => Ok(message.content),
+            (None, None) => Ok(String::new()),
+        }
+    }
+
+    /// The tokens of one line of the response stream.
+    fn into_tokens(self) -> Result<Vec<StreamToken>> {
+        let truncated = self.truncated();
+        let mut tokens = vec![StreamToken::Text(self.into_content()?)];
+        if truncated {
+            tokens.push(StreamToken::Truncated);
+        }
+        Ok(tokens)
+    }
+
+    fn truncated(&self) -> bool {
+        self.done_reason.as_deref() == Some("length")
+    }
+
+    fn into_turn(self) -> Result<AssistantTurn> {
+        let truncated = self.truncated();
+        let message = match (self.error, self.message) {
+            (Some(error), _) => return Err(RagError::LlmFailed(error)),
+            (None, Some(message)) => message,
+            (None, None) => return Err(RagError::LlmFailed("No message in response".to_string())),
+        };
+
+        // Ollama doesn't assign ids to tool calls, they must stay unique over
+        // all steps of an agent turn so that calls and results can be paired
+        let tool_calls = message
+            .tool_calls
+            .into_iter()
+            .map(|call| ToolCall {
+                id: format!("call_{}", uuid::Uuid::now_v7().simple()),
+                name: call.function.name,
+                arguments: call.function.arguments,
+            })
+            .collect();
+
+        Ok(AssistantTurn {
+            content: message.content,
+            tool_calls,
+            truncated,
+        })
+    }
+}
+
+impl OllamaProvider {
+    pub fn new() -> Self {
+        let api_url =
+            env::var("LLM_API_URL").unwrap_or_else(|_| "http://localhost:11434".to_string());
+
+        Self {
+            client: reqwest::Client::new(),
+            api_url,
+        }
+    }
+
+    async fn send(
+        &self,
+        request: &LlmRequest,
+        tools: &[ToolDefinition],
+        stream: bool,
+    ) -> Result<reqwest::Response> {
+        let messages = request
+            .messages
+            .iter()
+            .map(|message| OllamaMessage {
+                role: match message.role {
+                    LlmRole::System => "system",
+                    LlmRole::User => "user",
+                    LlmRole::Assistant => "assistant",
+                    LlmRole::Tool => "tool",
+                },
+                content: &message.content,
+                tool_calls: message
+                    .tool_calls
+                    .iter()
+                    .map(|call| OllamaToolCall {
+                        function: OllamaFunctionCall {
+                            name: call.name.clone(),
+                            arguments: call.arguments.clone(),
+                        },
+                    })
+                    .collect(),
+            })
+            .collect();
+        let tools = tools
+            .iter()
+            .map(|tool| OllamaTool {
+                r#type: "function",
+                function: OllamaFunction {
+                    name: tool.name,
+                    description: tool.description,
+                    parameters: &tool.parameters,
+                },
+            })
+            .collect();
+
+        let response = self
+            .client
+            .post(format!("{}/api/chat", self.api_url.trim_end_matches('/')))
+            .json(&OllamaChatRequest {
+                model: &request.params.model,
+                messages,
+                stream,
+                options: OllamaOptions {
+                    temperature: request.params.temperature,
+                    top_p: request.params.top_p,
+                    num_predict: request.params.max_tokens,
+                    stop: &request.params.stop,
+                    seed: request.params.seed,
+                },
+                tools,
+            })
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(response)
+    }
+}
+
+/// Parses the newline-delimited JSON of a streamed response into tokens.
+fn ndjson_tokens(
+    bytes: impl Stream<Item = reqwest::Result<Bytes>> + Send + 'static,
+) -> TokenStream {
+    async_stream::stream! {
+        let mut bytes = Box::pin(bytes);
+        let mut buffer = Vec::new();
+        loop {
+            let finished = match bytes.next().await {
+                Some(Ok(chunk)) => {
+                    buffer.extend_from_slice(&chunk);
+                    false
+                }
+                Some(Err(err)) => {
+                    yield Err(RagError::from(err));
+                    return;
+                }
+                None => true,
+            };
+
+            // The last line may end without a newline and still hold the `done_reason`
+            while let Some(end) = buffer
+                .iter()
+                .position(|&byte| byte == b'\n')
+                .map(|newline| newline + 1)
+                .or((finished && !buffer.is_empty()).then_some(buffer.len()))
+            {
+                let line: Vec<u8> = buffer.drain(..end).collect();
+                if line.iter().all(u8::is_ascii_whitespace) {
+                    continue;
+                }
+                match serde_json::from_slice::<OllamaChatResponse>(&line)
+                    .map_err(RagError::from)
+                    .and_then(OllamaChatResponse::into_tokens)
+                {
+                    Ok(tokens) => {
+                        for token in tokens {
+                            yield Ok(token);
+                        }
+                    }
+                    Err(err) => yield Err(err),
+                }
+            }
+
+            if finished {
+                return;
+            }
+        }
+    }
+    .boxed()
+}
+
+#[async_trait]
+impl LlmProvider for OllamaProvider {
+    fn name(&self) -> &'static str {
+        "ollama"
+    }
+
+    fn default_model(&self) -> &'static str {
+        "gemma2:2b"
+    }
+
+    async fn chat(&self, request: LlmRequest) -> Result<String> {
+        self.send(&request, &[], false)
+            .await?
+            .json::<OllamaChatResponse>()
+            .await?
+            .into_content()
+    }
+
+    async fn chat_stream(&self, request: LlmRequest) -> Result<TokenStream> {
+        let bytes = self.send(&request, &[], true).await?.bytes_stream();
+        Ok(ndjson_tokens(bytes))
+    }
+
+    async fn chat_with_tools(
+        &self,
+        request: LlmRequest,
+        tools: &[ToolDefinition],
+    ) -> Result<AssistantTurn> {
+        self.send(&request, tools, false)
+            .await?
+            .json::<OllamaChatResponse>()
+            .await?
+            .into_turn()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn parses_a_last_line_without_newline() {
+        let lines: Vec<reqwest::Result<Bytes>> = vec![
+            Ok(Bytes::from(
+                "{\"message\": {\"content\": \"Par\"}}\n{\"message\": {\"content\": \"is\"}}\n",
+            )),
+            Ok(Bytes::from(
+                "{\"message\": {\"content\": \"\"}, \"done\": true, \"done_reason\": \"length\"}",
+            )),
+        ];
+
+        let tokens: Vec<StreamToken> = ndjson_tokens(futures::stream::iter(lines))
+            .map(|token| token.unwrap())
+            .collect()
+            .await;
+
+        assert_eq!(
+            tokens,
+            [
+                StreamToken::Text("Par".to_owned()),
+                StreamToken::Text("is".to_owned()),
+                StreamToken::Text(String::new()),
+                StreamToken::Truncated,
+            ]
+        );
+    }
+}
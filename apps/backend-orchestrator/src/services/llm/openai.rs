@@ -0,0 +1,204 @@
+use super::{
+    AssistantTurn, LlmMessage, LlmProvider, LlmRequest, LlmRole, StreamToken, TokenStream,
+    ToolCall, ToolDefinition,
+};
+use crate::error::{RagError, Result};
+use async_openai::{
+    Client as OpenAiClient,
+    types::{
+        ChatCompletionMessageToolCall, ChatCompletionRequestAssistantMessage,
+        ChatCompletionRequestAssistantMessageContent, ChatCompletionRequestMessage,
+        ChatCompletionRequestSystemMessage, ChatCompletionRequestToolMessage,
+        ChatCompletionRequestUserMessage, ChatCompletionTool, ChatCompletionToolType,
+        CreateChatCompletionRequest, FinishReason, FunctionCall, FunctionObject, Stop,
+    },
+};
+use async_trait::async_trait;
+use futures::StreamExt;
+use std::env;
+
+/// Provider for OpenAI-compatible chat completion servers (llama.cpp, vLLM).
+pub struct OpenAiCompatibleProvider {
+    client: OpenAiClient<async_openai::config::OpenAIConfig>,
+}
+
+impl OpenAiCompatibleProvider {
+    pub fn new() -> Self {
+        let llm_api_url =
+            env::var("LLM_API_URL").unwrap_or_else(|_| "http://localhost:8080".to_string());
+        // llama.cpp doesn't require a real API key
+        let api_key = env::var("LLM_API_KEY").unwrap_or_else(|_| "dummy-key".to_string());
+
+        let config = async_openai::config::OpenAIConfig::new()
+            .with_api_base(llm_api_url)
+            .with_api_key(api_key);
+
+        Self {
+            client: OpenAiClient::with_config(config),
+        }
+    }
+
+    fn build_request(request: LlmRequest, stream: bool) -> CreateChatCompletionRequest {
+        let messages = request.messages.iter().map(to_openai_message).collect();
+        let params = request.params;
+
+        #[allow(deprecated)] // llama.cpp reads `max_tokens`, not `max_completion_tokens`
+        CreateChatCompletionRequest {
+            model: params.model,
+            messages,
+            stream: Some(stream),
+            temperature: Some(params.temperature),
+            top_p: params.top_p,
+            max_tokens: Some(params.max_tokens),
+            stop: (!params.stop.is_empty()).then_some(Stop::StringArray(params.stop)),
+            seed: params.seed,
+            ..Default::default()
+        }
+    }
+}
+
+#[async_trait]
+impl LlmProvider for OpenAiCompatibleProvider {
+    fn name(&self) -> &'static str {
+        "openai"
+    }
+
+    fn default_model(&self) -> &'static str {
+        "gemma-2-2b-it"
+    }
+
+    async fn chat(&self, request: LlmRequest) -> Result<String> {
+        let response = self
+            .client
+            .chat()
+            .create(Self::build_request(request, false))
+            .await
+            .map_err(|e| RagError::LlmFailed(e.to_string()))?;
+
+        response
+            .choices
+            .first()
+            .and_then(|choice| choice.message.content.clone())
+            .ok_or_else(|| RagError::LlmFailed("No content in response".to_string()))
+    }
+
+    async fn chat_stream(&self, request: LlmRequest) -> Result<TokenStream> {
+        let stream = self
+            .client
+            .chat()
+            .create_stream(Self::build_request(request, true))
+            .await
+            .map_err(|e| RagError::LlmFailed(e.to_string()))?;
+
+        let token_stream = stream.flat_map(|chunk_result| {
+            let tokens = match chunk_result {
+                Ok(chunk) => match chunk.choices.first() {
+                    Some(choice) => {
+                        let text = choice.delta.content.clone().unwrap_or_default();
+                        let mut tokens = vec![Ok(StreamToken::Text(text))];
+                        if choice.finish_reason == Some(FinishReason::Length) {
+                            tokens.push(Ok(StreamToken::Truncated));
+                        }
+                        tokens
+                    }
+                    None => Vec::new(),
+                },
+                Err(e) => vec![Err(RagError::LlmFailed(e.to_string()))],
+            };
+            futures::stream::iter(tokens)
+        });
+
+        Ok(token_stream.boxed())
+    }
+
+    async fn chat_with_tools(
+        &self,
+        request: LlmRequest,
+        tools: &[ToolDefinition],
+    ) -> Result<AssistantTurn> {
+        let mut request = Self::build_request(request, false);
+        request.tools = Some(tools.iter().map(to_openai_tool).collect());
+
+        let response = self
+            .client
+            .chat()
+            .create(request)
+            .await
+            .map_err(|e| RagError::LlmFailed(e.to_string()))?;
+
+        let choice = response
+            .choices
+            .into_iter()
+            .next()
+            .ok_or_else(|| RagError::LlmFailed("No choice in response".to_string()))?;
+        let truncated = choice.finish_reason == Some(FinishReason::Length);
+        let message = choice.message;
+
+        let tool_calls = message
+            .tool_calls
+            .unwrap_or_default()
+            .into_iter()
+            .map(|call| ToolCall {
+                // Malformed arguments are passed on as a string, the tool reports the error
+                arguments: serde_json::from_str(&call.function.arguments)
+                    .unwrap_or(serde_json::Value::String(call.function.arguments)),
+                id: call.id,
+                name: call.function.name,
+            })
+            .collect();
+
+        Ok(AssistantTurn {
+            content: message.content.unwrap_or_default(),
+            tool_calls,
+            truncated,
+        })
+    }
+}
+
+fn to_openai_message(message: &LlmMessage) -> ChatCompletionRequestMessage {
+    let content = message.content.as_str();
+    match message.role {
+        LlmRole::System => ChatCompletionRequestSystemMessage::from(content).into(),
+        LlmRole::User => ChatCompletionRequestUserMessage::from(content).into(),
+        LlmRole::Assistant if message.tool_calls.is_empty() => {
+            ChatCompletionRequestAssistantMessage::from(content).into()
+        }
+        LlmRole::Assistant => ChatCompletionRequestAssistantMessage {
+            content: (!content.is_empty())
+                .then(|| ChatCompletionRequestAssistantMessageContent::from(content)),
+            tool_calls: Some(
+                message
+                    .tool_calls
+                    .iter()
+                    .map(|call| ChatCompletionMessageToolCall {
+                        id: call.id.clone(),
+                        r#type: ChatCompletionToolType::Function,
+                        function: FunctionCall {
+                            name: call.name.clone(),
+                            arguments: call.arguments.to_string(),
+                        },
+                    })
+                    .collect(),
+            ),
+            ..Default::default()
+        }
+        .into(),
+        LlmRole::Tool => ChatCompletionRequestToolMessage {
+            content: content.into(),
+            tool_call_id: message.tool_call_id.clone().unwrap_or_default(),
+        }
+        .into(),
+    }
+}
+
+fn to_openai_tool(tool: &ToolDefinition) -> ChatCompletionTool {
+    ChatCompletionTool {
+        r#type: ChatCompletionToolType::Function,
+        function: FunctionObject {
+            name: tool.name.to_owned(),
+            description: Some(tool.description.to_owned()),
+            parameters: Some(tool.parameters.clone()),
+            strict: None,
+        },
+    }
+}
@@ -1,11 +1,36 @@
 use crate::models::{self, InputDocument};
 use meilisearch_sdk::settings::{Embedder, EmbedderSource, Settings};
-use meilisearch_sdk::{client::Client, search::SearchQuery};
+use meilisearch_sdk::{
+    client::Client,
+    documents::{DocumentDeletionQuery, DocumentsQuery},
+    search::{SearchQuery, Selectors},
+};
 use std::collections::HashMap;
 use std::env;
 
 const INDEX_NAME: &str = "rag_documents";
 
+/// Documents fetched per request when paging through all chunks of a document.
+const DOCUMENTS_PAGE_SIZE: usize = 1000;
+
+/// Attributes that can be used in search filters.
+pub const FILTERABLE_ATTRIBUTES: &[&str] = &[
+    "id",
+    "document_id",
+    "metadata.document_type",
+    "metadata.author",
+    "metadata.tags",
+    "metadata.language",
+    "metadata.created_at",
+    "metadata.updated_at",
+    "created_at_timestamp",
+    "source_file",
+    "chunk_index",
+    "hierarchy_lvl1",
+    "hierarchy_lvl2",
+    "hierarchy_lvl3",
+];
+
 pub struct MeilisearchService {
     client: Client,
 }
@@ -16,7 +41,15 @@ impl MeilisearchService {
             env::var("MEILISEARCH_URL").unwrap_or_else(|_| "http://localhost:7700".to_string());
         let meilisearch_api_key = env::var("MEILISEARCH_API_KEY").ok();
 
-        let client = Client::new(meilisearch_url, meilisearch_api_key)?;
+        Self::connect(meilisearch_url, meilisearch_api_key).await
+    }
+
+    /// Connects to the Meilisearch instance at `url` and configures the index.
+    pub async fn connect(
+        url: String,
+        api_key: Option<String>,
+    ) -> Result<MeilisearchService, meilisearch_sdk::errors::Error> {
+        let client = Client::new(url, api_key)?;
 
         let service = Self { client };
         service.initialize_index().await?;
@@ -56,18 +89,10 @@ impl MeilisearchService {
             "hierarchy_lvl3".to_owned(),
         ];
 
-        let filterable_attributes = vec![
-            "document_type".to_owned(),
-            "author".to_owned(),
-            "created_at".to_owned(),
-            "updated_at".to_owned(),
-            "tags".to_owned(),
-            "source_file".to_owned(),
-            "chunk_index".to_owned(),
-            "hierarchy_lvl1".to_owned(),
-            "hierarchy_lvl2".to_owned(),
-            "hierarchy_lvl3".to_owned(),
-        ];
+        let filterable_attributes = FILTERABLE_ATTRIBUTES
+            .iter()
+            .map(|attribute| attribute.to_string())
+            .collect::<Vec<_>>();
 
         let sortable_attributes = vec![
             "created_at".to_owned(),
@@ -135,6 +160,33 @@ impl MeilisearchService {
         &self,
         query: &str,
         limit: usize,
+        semantic_ratio: f32,
+        filters: Option<&str>,
+    ) -> Result<Vec<models::SearchResult>, meilisearch_sdk::errors::Error> {
+        self.search(query, None, limit, semantic_ratio, filters)
+            .await
+    }
+
+    /// Hybrid search where `query` only drives the keyword part and the semantic
+    /// part uses the given embedding instead of an embedding of `query`.
+    pub async fn hybrid_search_with_vector(
+        &self,
+        query: &str,
+        vector: &[f32],
+        limit: usize,
+        semantic_ratio: f32,
+        filters: Option<&str>,
+    ) -> Result<Vec<models::SearchResult>, meilisearch_sdk::errors::Error> {
+        self.search(query, Some(vector), limit, semantic_ratio, filters)
+            .await
+    }
+
+    async fn search(
+        &self,
+        query: &str,
+        vector: Option<&[f32]>,
+        limit: usize,
+        semantic_ratio: f32,
         filters: Option<&str>,
     ) -> Result<Vec<models::SearchResult>, meilisearch_sdk::errors::Error> {
         let index = self.client.index(INDEX_NAME);
@@ -142,9 +194,14 @@ impl MeilisearchService {
         let mut search_query = SearchQuery::new(&index)
             .with_query(query)
             .with_limit(limit)
-            .with_hybrid("default", 0.5)
+            .with_hybrid("default", semantic_ratio)
+            .with_show_ranking_score(true)
             .build();
 
+        if let Some(vector) = vector {
+            search_query = search_query.with_vector(vector).build();
+        }
+
         if let Some(filter) = filters {
             search_query = search_query.with_filter(filter).build();
         }
@@ -158,37 +215,331 @@ impl MeilisearchService {
         );
         Ok(search_results
             .hits
-            .iter()
-            .map(|hit| hit.result.to_owned())
+            .into_iter()
+            .map(|hit| models::SearchResult {
+                ranking_score: hit.ranking_score,
+                ..hit.result
+            })
             .collect())
     }
 
-    pub async fn list_documents(
+    /// Returns all chunks of a document in document order, only those that
+    /// also match `scope` if given.
+    ///
+    /// Pages through the documents endpoint, searches are capped at
+    /// `maxTotalHits` and would cut off long documents.
+    pub async fn document_chunks(
         &self,
-    ) -> Result<Vec<crate::models::DocumentInfo>, meilisearch_sdk::errors::Error> {
+        document_id: &str,
+        scope: Option<&str>,
+    ) -> Result<Vec<models::SearchResult>, meilisearch_sdk::errors::Error> {
         let index = self.client.index(INDEX_NAME);
+        let filter = match scope {
+            Some(scope) => format!("{} AND ({})", document_filter(document_id), scope),
+            None => document_filter(document_id),
+        };
+
+        let mut chunks: Vec<models::SearchResult> = Vec::new();
+        loop {
+            let page = DocumentsQuery::new(&index)
+                .with_filter(&filter)
+                .with_offset(chunks.len())
+                .with_limit(DOCUMENTS_PAGE_SIZE)
+                .execute::<models::SearchResult>()
+                .await?;
+            let fetched = page.results.len();
+            chunks.extend(page.results);
+
+            if fetched < DOCUMENTS_PAGE_SIZE || chunks.len() >= page.total as usize {
+                break;
+            }
+        }
+
+        chunks.sort_by_key(|chunk| chunk.chunk_index);
+        Ok(chunks)
+    }
+
+    /// Returns the chunks of a document with a `chunk_index` in `range`, in document order.
+    pub async fn chunk_range(
+        &self,
+        document_id: &str,
+        range: std::ops::RangeInclusive<usize>,
+    ) -> Result<Vec<models::SearchResult>, meilisearch_sdk::errors::Error> {
+        let index = self.client.index(INDEX_NAME);
+        let filter = format!(
+            "{} AND chunk_index {} TO {}",
+            document_filter(document_id),
+            range.start(),
+            range.end()
+        );
 
-        // Get all documents and group by source_file to create document list
         let search_results = SearchQuery::new(&index)
             .with_query("")
-            .with_limit(1000)
+            .with_filter(&filter)
+            .with_sort(&["chunk_index:asc"])
+            .with_limit(range.count())
             .execute::<models::SearchResult>()
             .await?;
 
         Ok(search_results
             .hits
-            .iter()
-            .map(|hit| crate::models::DocumentInfo {
-                id: hit.result.id.to_owned(),
-                title: hit.result.title.to_owned(),
-                status: "indexed".to_owned(),
-                created_at: hit
-                    .result
-                    .metadata
-                    .created_at
-                    .to_owned()
-                    .unwrap_or_default(),
+            .into_iter()
+            .map(|hit| hit.result)
+            .collect())
+    }
+
+    /// Returns the summary stored with the first chunk of a document, if any.
+    pub async fn document_summary(
+        &self,
+        document_id: &str,
+    ) -> Result<Option<String>, meilisearch_sdk::errors::Error> {
+        #[derive(serde::Deserialize)]
+        struct StoredSummary {
+            summary: Option<String>,
+        }
+
+        let index = self.client.index(INDEX_NAME);
+        let filter = document_filter(document_id);
+
+        let search_results = SearchQuery::new(&index)
+            .with_query("")
+            .with_filter(&filter)
+            .with_sort(&["chunk_index:asc"])
+            .with_attributes_to_retrieve(Selectors::Some(&["summary"]))
+            .with_limit(1)
+            .execute::<StoredSummary>()
+            .await?;
+
+        Ok(search_results
+            .hits
+            .into_iter()
+            .next()
+            .and_then(|hit| hit.result.summary))
+    }
+
+    /// Stores a document summary with the document's first chunk. It is removed
+    /// together with the chunks when the document is deleted or replaced.
+    pub async fn store_document_summary(
+        &self,
+        chunk_id: &str,
+        summary: &str,
+    ) -> Result<(), meilisearch_sdk::errors::Error> {
+        let index = self.client.index(INDEX_NAME);
+
+        index
+            .add_or_update(
+                &[serde_json::json!({ "id": chunk_id, "summary": summary })],
+                Some("id"),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Deletes all chunks of a document and waits until Meilisearch applied the deletion.
+    pub async fn delete_document(
+        &self,
+        document_id: &str,
+    ) -> Result<(), meilisearch_sdk::errors::Error> {
+        let index = self.client.index(INDEX_NAME);
+        let filter = document_filter(document_id);
+
+        index
+            .delete_documents_with(DocumentDeletionQuery::new(&index).with_filter(&filter))
+            .await?
+            .wait_for_completion(&self.client, None, None)
+            .await?;
+        tracing::info!("Deleted document {}", document_id);
+
+        Ok(())
+    }
+
+    /// Hybrid search returning each hit with Meilisearch's ranking score
+    /// details and the terms that matched in its title or content.
+    pub async fn hybrid_search_explained(
+        &self,
+        query: &str,
+        limit: usize,
+        semantic_ratio: f32,
+        filters: Option<&str>,
+    ) -> Result<Vec<(models::SearchResult, models::HitDetails)>, meilisearch_sdk::errors::Error>
+    {
+        let index = self.client.index(INDEX_NAME);
+
+        let mut search_query = SearchQuery::new(&index)
+            .with_query(query)
+            .with_limit(limit)
+            .with_hybrid("default", semantic_ratio)
+            .with_show_ranking_score(true)
+            .with_show_ranking_score_details(true)
+            .with_show_matches_position(true)
+            .build();
+
+        if let Some(filter) = filters {
+            search_query = search_query.with_filter(filter).build();
+        }
+
+        let search_results = search_query.execute::<models::SearchResult>().await?;
+
+        Ok(search_results
+            .hits
+            .into_iter()
+            .map(|hit| {
+                let mut matched_terms: Vec<String> = Vec::new();
+                for (field, ranges) in hit.matches_position.iter().flatten() {
+                    let text = match field.as_str() {
+                        "title" => &hit.result.title,
+                        "content" => &hit.result.content,
+                        _ => continue,
+                    };
+                    for range in ranges {
+                        if let Some(term) = text.get(range.start..range.start + range.length) {
+                            let term = term.to_lowercase();
+                            if !matched_terms.contains(&term) {
+                                matched_terms.push(term);
+                            }
+                        }
+                    }
+                }
+
+                let details = models::HitDetails {
+                    ranking_score_details: hit.ranking_score_details,
+                    matched_terms,
+                };
+                (
+                    models::SearchResult {
+                        ranking_score: hit.ranking_score,
+                        ..hit.result
+                    },
+                    details,
+                )
             })
             .collect())
     }
+
+    /// Lists the uploaded documents, only those with chunks matching `filter` if
+    /// given. Records without a `document_id` are listed on their own.
+    pub async fn list_documents(
+        &self,
+        filter: Option<&str>,
+    ) -> Result<Vec<crate::models::DocumentInfo>, meilisearch_sdk::errors::Error> {
+        #[derive(serde::Deserialize)]
+        struct StoredChunk {
+            id: String,
+            title: String,
+            document_id: Option<String>,
+            #[serde(default)]
+            metadata: models::DocumentMetadata,
+        }
+
+        let index = self.client.index(INDEX_NAME);
+        let mut documents: Vec<crate::models::DocumentInfo> = Vec::new();
+        let mut seen = std::collections::HashSet::new();
+        let mut offset = 0;
+
+        // Pages through all chunks, a search is capped at `maxTotalHits`
+        loop {
+            let mut query = DocumentsQuery::new(&index);
+            query
+                .with_fields(["id", "title", "document_id", "metadata"])
+                .with_offset(offset)
+                .with_limit(DOCUMENTS_PAGE_SIZE);
+            if let Some(filter) = filter {
+                query.with_filter(filter);
+            }
+            let page = query.execute::<StoredChunk>().await?;
+            let fetched = page.results.len();
+            offset += fetched;
+
+            for chunk in page.results {
+                let id = chunk.document_id.unwrap_or(chunk.id);
+                if seen.insert(id.clone()) {
+                    documents.push(crate::models::DocumentInfo {
+                        id,
+                        title: chunk.title,
+                        status: "indexed".to_owned(),
+                        created_at: chunk.metadata.created_at.unwrap_or_default(),
+                    });
+                }
+            }
+
+            if fetched < DOCUMENTS_PAGE_SIZE || offset >= page.total as usize {
+                break;
+            }
+        }
+
+        Ok(documents)
+    }
+}
+
+fn document_filter(document_id: &str) -> String {
+    format!(
+        "document_id = \"{}\"",
+        document_id.replace('\\', "\\\\").replace('"', "\\\"")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{FakeBackend, chunk};
+
+    #[tokio::test]
+    async fn document_chunks_pages_past_the_search_limit_in_order() {
+        let count = DOCUMENTS_PAGE_SIZE * 2 + 17;
+        // Stored in reverse order, with another document in between
+        let mut chunks: Vec<_> = (0..count)
+            .rev()
+            .map(|index| chunk(&format!("long-{}", index), "long", index, "text", 0.5))
+            .collect();
+        chunks.insert(count / 2, chunk("short-0", "short", 0, "text", 0.5));
+        let backend = FakeBackend::start(chunks).await;
+        let service = MeilisearchService::connect(backend.url.clone(), None)
+            .await
+            .unwrap();
+
+        let chunks = service.document_chunks("long", None).await.unwrap();
+
+        assert_eq!(chunks.len(), count);
+        assert!(
+            chunks
+                .iter()
+                .enumerate()
+                .all(|(index, chunk)| chunk.chunk_index == Some(index))
+        );
+    }
+
+    #[tokio::test]
+    async fn list_documents_lists_each_document_once() {
+        let mut chunks: Vec<_> = (0..DOCUMENTS_PAGE_SIZE + 3)
+            .map(|index| chunk(&format!("long-{}", index), "long", index, "text", 0.5))
+            .collect();
+        chunks.push(chunk("short-0", "short", 0, "text", 0.5));
+        let mut legacy = chunk("legacy", "legacy", 0, "text", 0.5);
+        legacy
+            .document
+            .as_object_mut()
+            .unwrap()
+            .remove("document_id");
+        chunks.push(legacy);
+        let backend = FakeBackend::start(chunks).await;
+        let service = MeilisearchService::connect(backend.url.clone(), None)
+            .await
+            .unwrap();
+
+        let ids = |documents: Vec<models::DocumentInfo>| -> Vec<String> {
+            documents.into_iter().map(|document| document.id).collect()
+        };
+        assert_eq!(
+            ids(service.list_documents(None).await.unwrap()),
+            ["long", "short", "legacy"]
+        );
+        assert_eq!(
+            ids(service
+                .list_documents(Some("document_id = \"short\""))
+                .await
+                .unwrap()),
+            ["short"]
+        );
+    }
 }
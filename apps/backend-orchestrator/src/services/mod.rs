@@ -1,5 +1,17 @@
-// pub mod llm;
+pub mod answer_cache;
+pub mod embedding;
+pub mod feedback;
+pub mod generations;
+pub mod llm;
 pub mod meilisearch;
+pub mod reranker;
+pub mod session;
 
-// pub use llm::LlmService;
+pub use answer_cache::AnswerCache;
+pub use embedding::EmbeddingService;
+pub use feedback::FeedbackStore;
+pub use generations::{GenerationGuard, GenerationRegistry};
+pub use llm::LlmService;
 pub use meilisearch::MeilisearchService;
+pub use reranker::RerankerService;
+pub use session::SessionStore;
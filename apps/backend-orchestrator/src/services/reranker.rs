@@ -0,0 +1,259 @@
+use crate::error::Result;
+use crate::models::SearchResult;
+use serde::{Deserialize, Serialize};
+use std::env;
+
+/// Cross-encoder reranker speaking the Text Embeddings Inference `/rerank` protocol.
+///
+/// Disabled unless `RERANKER_API_URL` is set.
+pub struct RerankerService {
+    client: reqwest::Client,
+    api_url: Option<String>,
+    /// Number of candidates fetched from Meilisearch per requested result
+    overfetch_factor: usize,
+    /// Results with a lower (normalized) reranker score are dropped
+    score_threshold: f32,
+    /// Texts per `/rerank` request, TEI rejects more than its `max_client_batch_size`
+    max_batch: usize,
+}
+
+#[derive(Serialize)]
+struct RerankRequest<'a> {
+    query: &'a str,
+    texts: Vec<&'a str>,
+    truncate: bool,
+}
+
+#[derive(Deserialize)]
+struct RerankScore {
+    index: usize,
+    score: f32,
+}
+
+impl RerankerService {
+    pub fn new() -> Self {
+        let api_url = env::var("RERANKER_API_URL")
+            .ok()
+            .filter(|url| !url.is_empty());
+        let overfetch_factor = env::var("RERANKER_OVERFETCH_FACTOR")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(4);
+        let score_threshold = env::var("RERANKER_SCORE_THRESHOLD")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(0.1);
+        let max_batch = env::var("RERANKER_MAX_BATCH")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(32);
+
+        Self::with_settings(api_url, overfetch_factor, score_threshold, max_batch)
+    }
+
+    pub fn with_settings(
+        api_url: Option<String>,
+        overfetch_factor: usize,
+        score_threshold: f32,
+        max_batch: usize,
+    ) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            api_url,
+            overfetch_factor,
+            score_threshold,
+            max_batch: max_batch.max(1),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.api_url.is_some()
+    }
+
+    /// Number of search candidates to fetch for `limit` final results.
+    pub fn candidate_limit(&self, limit: usize) -> usize {
+        if self.is_enabled() {
+            limit * self.overfetch_factor.max(1)
+        } else {
+            limit
+        }
+    }
+
+    /// Reorders `results` by cross-encoder score, drops results below the score
+    /// threshold and keeps at most `limit`. If the reranker is disabled or fails,
+    /// the Meilisearch order is kept.
+    pub async fn rerank(
+        &self,
+        query: &str,
+        results: Vec<SearchResult>,
+        limit: usize,
+    ) -> Vec<SearchResult> {
+        let Some(api_url) = &self.api_url else {
+            return results.into_iter().take(limit).collect();
+        };
+        if results.is_empty() {
+            return results;
+        }
+
+        let scores = match self.score(api_url, query, &results).await {
+            Ok(scores) => scores,
+            Err(err) => {
+                tracing::warn!("Reranking failed, keeping Meilisearch order: {}", err);
+                return results.into_iter().take(limit).collect();
+            }
+        };
+
+        let mut slots: Vec<Option<SearchResult>> = results.into_iter().map(Some).collect();
+        let mut reranked = Vec::new();
+
+        for RerankScore { index, score } in scores {
+            if score < self.score_threshold {
+                continue;
+            }
+            if let Some(mut result) = slots.get_mut(index).and_then(Option::take) {
+                result.rerank_score = Some(score);
+                reranked.push(result);
+            }
+        }
+
+        reranked.sort_by(|a, b| {
+            b.rerank_score
+                .unwrap_or(0.0)
+                .total_cmp(&a.rerank_score.unwrap_or(0.0))
+        });
+        tracing::info!(
+            "Reranked {} candidates, {} above threshold {}",
+            slots.len(),
+            reranked.len(),
+            self.score_threshold
+        );
+        reranked.truncate(limit);
+
+        reranked
+    }
+
+    /// Scores the results in batches of at most `max_batch` texts, with the
+    /// indices of the returned scores referring to `results`.
+    async fn score(
+        &self,
+        api_url: &str,
+        query: &str,
+        results: &[SearchResult],
+    ) -> Result<Vec<RerankScore>> {
+        let url = format!("{}/rerank", api_url.trim_end_matches('/'));
+        let mut scores = Vec::with_capacity(results.len());
+
+        for (batch_index, batch) in results.chunks(self.max_batch).enumerate() {
+            let request = RerankRequest {
+                query,
+                texts: batch.iter().map(|result| result.content.as_str()).collect(),
+                truncate: true,
+            };
+
+            let batch_scores = self
+                .client
+                .post(&url)
+                .json(&request)
+                .send()
+                .await?
+                .error_for_status()?
+                .json::<Vec<RerankScore>>()
+                .await?;
+
+            let offset = batch_index * self.max_batch;
+            scores.extend(batch_scores.into_iter().map(|score| RerankScore {
+                index: offset + score.index,
+                score: score.score,
+            }));
+        }
+
+        Ok(scores)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::DocumentMetadata;
+    use crate::test_support::FakeReranker;
+
+    fn result(id: &str, content: &str) -> SearchResult {
+        SearchResult {
+            id: id.to_owned(),
+            title: id.to_owned(),
+            content: content.to_owned(),
+            metadata: DocumentMetadata::default(),
+            source_file: None,
+            document_id: None,
+            chunk_index: None,
+            rerank_score: None,
+            ranking_score: None,
+            merged_chunk_ids: Vec::new(),
+        }
+    }
+
+    fn ids(results: &[SearchResult]) -> Vec<&str> {
+        results.iter().map(|result| result.id.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn reorders_by_score_and_drops_results_below_the_threshold() {
+        let reranker = FakeReranker::start(32).await;
+        let service = RerankerService::with_settings(Some(reranker.url.clone()), 4, 0.4, 32);
+
+        let reranked = service
+            .rerank(
+                "hybrid search ranking",
+                vec![
+                    result("weather", "The weather is sunny."),
+                    result("partial", "Hybrid retrieval with dense vectors."),
+                    result("best", "Hybrid search ranking."),
+                    result("good", "Search ranking explained."),
+                ],
+                10,
+            )
+            .await;
+
+        assert_eq!(ids(&reranked), ["best", "good"]);
+        assert!(reranked[0].rerank_score > reranked[1].rerank_score);
+    }
+
+    #[tokio::test]
+    async fn splits_candidates_into_batches() {
+        let reranker = FakeReranker::start(3).await;
+        let service = RerankerService::with_settings(Some(reranker.url.clone()), 4, 0.0, 3);
+        let results: Vec<_> = (0..8)
+            .map(|index| {
+                let words = ["ranking"; 8][..index].join(" ");
+                result(&index.to_string(), &format!("{} text", words))
+            })
+            .collect();
+
+        let reranked = service.rerank("ranking", results, 5).await;
+
+        assert_eq!(ids(&reranked), ["7", "6", "5", "4", "3"]);
+        assert_eq!(reranker.batch_sizes(), [3, 3, 2]);
+    }
+
+    #[tokio::test]
+    async fn keeps_the_search_order_when_reranking_fails() {
+        // Batches larger than the server accepts are rejected like TEI does
+        let reranker = FakeReranker::start(2).await;
+        let service = RerankerService::with_settings(Some(reranker.url.clone()), 4, 0.0, 32);
+
+        let reranked = service
+            .rerank(
+                "ranking",
+                vec![
+                    result("first", "unrelated"),
+                    result("second", "ranking"),
+                    result("third", "ranking ranking"),
+                ],
+                2,
+            )
+            .await;
+
+        assert_eq!(ids(&reranked), ["first", "second"]);
+        assert!(reranked.iter().all(|result| result.rerank_score.is_none()));
+    }
+}
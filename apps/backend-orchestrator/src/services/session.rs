@@ -0,0 +1,184 @@
+use crate::models::{ChatMessage, ChatRole, Citation, SearchResult, Session, SessionSummary};
+use chrono::{DateTime, Duration, Utc};
+use std::collections::HashMap;
+use std::env;
+use tokio::sync::RwLock;
+
+/// Number of previous messages that are replayed to the LLM for a new turn.
+const MAX_HISTORY_MESSAGES: usize = 10;
+
+/// In-memory store for multi-turn chat sessions.
+///
+/// Sessions idle for longer than `SESSION_IDLE_TTL_SECS` (default 24 h) are
+/// dropped; beyond `SESSION_MAX_COUNT` sessions (default 1000) the least
+/// recently updated one is evicted for a new one.
+pub struct SessionStore {
+    sessions: RwLock<HashMap<String, Session>>,
+    max_sessions: usize,
+    idle_ttl: Duration,
+}
+
+impl SessionStore {
+    pub fn new() -> Self {
+        let max_sessions = env::var("SESSION_MAX_COUNT")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(1000);
+        let idle_ttl_secs = env::var("SESSION_IDLE_TTL_SECS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(24 * 60 * 60);
+
+        Self::with_limits(max_sessions, Duration::seconds(idle_ttl_secs))
+    }
+
+    pub fn with_limits(max_sessions: usize, idle_ttl: Duration) -> Self {
+        Self {
+            sessions: RwLock::new(HashMap::new()),
+            max_sessions: max_sessions.max(1),
+            idle_ttl,
+        }
+    }
+
+    fn is_expired(&self, session: &Session, now: DateTime<Utc>) -> bool {
+        now - session.updated_at > self.idle_ttl
+    }
+
+    /// Returns the most recent messages of a session, oldest first.
+    pub async fn history(&self, session_id: &str) -> Vec<ChatMessage> {
+        let now = Utc::now();
+        let sessions = self.sessions.read().await;
+
+        sessions
+            .get(session_id)
+            .filter(|session| !self.is_expired(session, now))
+            .map(|session| {
+                let skip = session.messages.len().saturating_sub(MAX_HISTORY_MESSAGES);
+                session.messages[skip..].to_vec()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Appends a completed question/answer turn, creating the session if needed.
+    pub async fn append_turn(
+        &self,
+        session_id: &str,
+        question: &str,
+        answer: &str,
+        sources: Vec<SearchResult>,
+        citations: Vec<Citation>,
+    ) {
+        let now = Utc::now();
+        let mut sessions = self.sessions.write().await;
+
+        sessions.retain(|_, session| !self.is_expired(session, now));
+        if !sessions.contains_key(session_id) && sessions.len() >= self.max_sessions {
+            let oldest = sessions
+                .values()
+                .min_by_key(|session| session.updated_at)
+                .map(|session| session.id.clone());
+            if let Some(oldest) = oldest {
+                tracing::debug!("Evicting session {}", oldest);
+                sessions.remove(&oldest);
+            }
+        }
+
+        let session = sessions
+            .entry(session_id.to_owned())
+            .or_insert_with(|| Session {
+                id: session_id.to_owned(),
+                title: question.chars().take(80).collect(),
+                messages: Vec::new(),
+                created_at: now,
+                updated_at: now,
+            });
+
+        session.messages.push(ChatMessage {
+            role: ChatRole::User,
+            content: question.to_owned(),
+            sources: Vec::new(),
+            citations: Vec::new(),
+            created_at: now,
+        });
+        session.messages.push(ChatMessage {
+            role: ChatRole::Assistant,
+            content: answer.to_owned(),
+            sources,
+            citations,
+            created_at: now,
+        });
+        session.updated_at = now;
+    }
+
+    pub async fn list(&self) -> Vec<SessionSummary> {
+        let now = Utc::now();
+        let sessions = self.sessions.read().await;
+
+        let mut summaries: Vec<SessionSummary> = sessions
+            .values()
+            .filter(|session| !self.is_expired(session, now))
+            .map(|session| SessionSummary {
+                id: session.id.to_owned(),
+                title: session.title.to_owned(),
+                message_count: session.messages.len(),
+                created_at: session.created_at,
+                updated_at: session.updated_at,
+            })
+            .collect();
+
+        summaries.sort_by_key(|summary| std::cmp::Reverse(summary.updated_at));
+        summaries
+    }
+
+    pub async fn get(&self, session_id: &str) -> Option<Session> {
+        self.sessions
+            .read()
+            .await
+            .get(session_id)
+            .filter(|session| !self.is_expired(session, Utc::now()))
+            .cloned()
+    }
+
+    pub async fn delete(&self, session_id: &str) -> bool {
+        self.sessions.write().await.remove(session_id).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn append(store: &SessionStore, session_id: &str) {
+        store
+            .append_turn(session_id, "question", "answer", Vec::new(), Vec::new())
+            .await;
+    }
+
+    #[tokio::test]
+    async fn evicts_least_recently_updated_session_at_max_count() {
+        let store = SessionStore::with_limits(2, Duration::hours(1));
+        append(&store, "a").await;
+        append(&store, "b").await;
+        append(&store, "a").await;
+
+        append(&store, "c").await;
+
+        assert!(store.get("a").await.is_some());
+        assert!(store.get("b").await.is_none());
+        assert!(store.get("c").await.is_some());
+        assert_eq!(store.history("a").await.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn drops_idle_sessions() {
+        let store = SessionStore::with_limits(10, Duration::zero());
+        append(&store, "a").await;
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+
+        assert!(store.history("a").await.is_empty());
+        assert!(store.list().await.is_empty());
+
+        append(&store, "b").await;
+        assert_eq!(store.sessions.read().await.len(), 1);
+    }
+}
@@ -0,0 +1,358 @@
+//! In-process stand-ins for Meilisearch, the embedding API and the reranker,
+//! so the RAG pipeline can run in tests together with the mock LLM provider.
+
+use axum::{
+    Json, Router,
+    extract::{Query, State},
+    http::StatusCode,
+    routing::{get, patch, post},
+};
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// An indexed chunk with the `_rankingScore` the fake search returns for it.
+#[derive(Debug, Clone)]
+pub struct FakeChunk {
+    pub document: Value,
+    pub score: f64,
+}
+
+pub fn chunk(
+    id: &str,
+    document_id: &str,
+    chunk_index: usize,
+    content: &str,
+    score: f64,
+) -> FakeChunk {
+    FakeChunk {
+        document: json!({
+            "id": id,
+            "title": format!("{}.md", document_id),
+            "content": content,
+            "metadata": { "language": "en" },
+            "document_id": document_id,
+            "chunk_index": chunk_index,
+        }),
+        score,
+    }
+}
+
+type Documents = Arc<Mutex<Vec<FakeChunk>>>;
+
+/// A fake Meilisearch and embedding API on a random local port.
+///
+/// Searches ignore the query and return the matching chunks by score;
+/// filters support `attribute = "value"`, `attribute IN [...]` and
+/// `attribute from TO to` clauses joined with `AND`.
+pub struct FakeBackend {
+    pub url: String,
+}
+
+impl FakeBackend {
+    pub async fn start(chunks: Vec<FakeChunk>) -> Self {
+        let documents: Documents = Arc::new(Mutex::new(chunks));
+
+        let app = Router::new()
+            .route("/indexes/rag_documents", get(index_info))
+            .route("/indexes/rag_documents/settings", patch(enqueued_task))
+            .route("/indexes/rag_documents/search", post(search))
+            .route(
+                "/indexes/rag_documents/documents/fetch",
+                post(fetch_documents),
+            )
+            .route(
+                "/indexes/rag_documents/documents",
+                get(list_documents).post(add_documents).put(add_documents),
+            )
+            .route("/api/embed", post(embed))
+            .with_state(documents);
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind fake backend");
+        let url = format!("http://{}", listener.local_addr().expect("local address"));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        Self { url }
+    }
+}
+
+async fn index_info() -> Json<Value> {
+    Json(json!({
+        "uid": "rag_documents",
+        "primaryKey": "id",
+        "createdAt": "2025-01-01T00:00:00Z",
+        "updatedAt": "2025-01-01T00:00:00Z",
+    }))
+}
+
+async fn enqueued_task() -> (StatusCode, Json<Value>) {
+    (
+        StatusCode::ACCEPTED,
+        Json(json!({
+            "taskUid": 1,
+            "indexUid": "rag_documents",
+            "status": "enqueued",
+            "type": "settingsUpdate",
+            "enqueuedAt": "2025-01-01T00:00:00Z",
+        })),
+    )
+}
+
+async fn search(State(documents): State<Documents>, Json(query): Json<Value>) -> Json<Value> {
+    let mut hits: Vec<FakeChunk> = matching(&documents, &query);
+    if query["sort"].to_string().contains("chunk_index:asc") {
+        hits.sort_by_key(|hit| hit.document["chunk_index"].as_u64());
+    } else {
+        hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+    }
+    let limit = query["limit"].as_u64().unwrap_or(20) as usize;
+
+    let hits: Vec<Value> = hits
+        .into_iter()
+        .take(limit)
+        .map(|hit| {
+            let mut document = hit.document;
+            document["_rankingScore"] = json!(hit.score);
+            document
+        })
+        .collect();
+
+    Json(json!({
+        "hits": hits,
+        "query": query["q"].as_str().unwrap_or_default(),
+        "processingTimeMs": 0,
+        "limit": limit,
+        "offset": 0,
+        "estimatedTotalHits": hits.len(),
+    }))
+}
+
+async fn fetch_documents(
+    State(documents): State<Documents>,
+    Json(query): Json<Value>,
+) -> Json<Value> {
+    let hits = matching(&documents, &query);
+    let offset = query["offset"].as_u64().unwrap_or(0) as usize;
+    let limit = query["limit"].as_u64().unwrap_or(20) as usize;
+
+    let results: Vec<Value> = hits
+        .iter()
+        .skip(offset)
+        .take(limit)
+        .map(|hit| hit.document.clone())
+        .collect();
+
+    Json(json!({
+        "results": results,
+        "offset": offset,
+        "limit": limit,
+        "total": hits.len(),
+    }))
+}
+
+/// Document queries without a filter use `GET documents` instead of `fetch`.
+async fn list_documents(
+    documents: State<Documents>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Json<Value> {
+    let number = |name: &str| params.get(name).and_then(|value| value.parse::<u64>().ok());
+    fetch_documents(
+        documents,
+        Json(json!({ "offset": number("offset"), "limit": number("limit") })),
+    )
+    .await
+}
+
+async fn add_documents(
+    State(documents): State<Documents>,
+    Json(added): Json<Vec<Value>>,
+) -> (StatusCode, Json<Value>) {
+    let mut stored = documents.lock().expect("fake documents lock");
+    for document in added {
+        match stored
+            .iter_mut()
+            .find(|chunk| chunk.document["id"] == document["id"])
+        {
+            Some(chunk) => {
+                for (key, value) in document.as_object().into_iter().flatten() {
+                    chunk.document[key] = value.clone();
+                }
+            }
+            None => stored.push(FakeChunk {
+                document,
+                score: 0.5,
+            }),
+        }
+    }
+
+    (
+        StatusCode::ACCEPTED,
+        Json(json!({
+            "taskUid": 2,
+            "indexUid": "rag_documents",
+            "status": "enqueued",
+            "type": "documentAdditionOrUpdate",
+            "enqueuedAt": "2025-01-01T00:00:00Z",
+        })),
+    )
+}
+
+/// Letter frequencies, so equal texts get equal embeddings and different
+/// texts mostly different ones.
+async fn embed(Json(request): Json<Value>) -> Json<Value> {
+    let text = request["input"][0]
+        .as_str()
+        .unwrap_or_default()
+        .to_lowercase();
+    let mut vector = vec![0.0_f32; 26];
+    for byte in text.bytes().filter(u8::is_ascii_lowercase) {
+        vector[(byte - b'a') as usize] += 1.0;
+    }
+
+    Json(json!({ "embeddings": [vector] }))
+}
+
+fn matching(documents: &Documents, query: &Value) -> Vec<FakeChunk> {
+    let filter = query["filter"].as_str().unwrap_or_default();
+
+    documents
+        .lock()
+        .expect("fake documents lock")
+        .iter()
+        .filter(|chunk| {
+            filter
+                .split(" AND ")
+                .all(|clause| matches_clause(&chunk.document, clause))
+        })
+        .cloned()
+        .collect()
+}
+
+fn matches_clause(document: &Value, clause: &str) -> bool {
+    let clause = clause.trim().trim_start_matches('(').trim_end_matches(')');
+    if clause.is_empty() {
+        return true;
+    }
+
+    let value_of = |attribute: &str| {
+        attribute
+            .trim()
+            .split('.')
+            .fold(document, |value, key| &value[key])
+            .clone()
+    };
+    let unquote = |value: &str| value.trim().trim_matches('"').replace("\\\"", "\"");
+
+    if let Some((attribute, value)) = clause.split_once(" = ") {
+        let actual = value_of(attribute);
+        return actual
+            .as_str()
+            .map(str::to_owned)
+            .unwrap_or(actual.to_string())
+            == unquote(value);
+    }
+    if let Some((attribute, values)) = clause.split_once(" IN ") {
+        let actual = value_of(attribute);
+        let actual = actual
+            .as_str()
+            .map(str::to_owned)
+            .unwrap_or(actual.to_string());
+        return values
+            .trim()
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .split(',')
+            .any(|value| unquote(value) == actual);
+    }
+    if let Some((attribute_from, to)) = clause.split_once(" TO ")
+        && let Some((attribute, from)) = attribute_from.trim().rsplit_once(' ')
+    {
+        let actual = value_of(attribute).as_f64();
+        let (from, to) = (from.parse::<f64>().ok(), to.trim().parse::<f64>().ok());
+        return matches!((actual, from, to), (Some(actual), Some(from), Some(to)) if from <= actual && actual <= to);
+    }
+
+    // Unsupported clauses don't restrict the fake
+    true
+}
+
+/// A fake Text Embeddings Inference `/rerank` endpoint on a random local port.
+///
+/// Scores each text by the share of its words that occur in the query and, like TEI,
+/// rejects requests with more texts than `max_batch`.
+pub struct FakeReranker {
+    pub url: String,
+    batch_sizes: Arc<Mutex<Vec<usize>>>,
+}
+
+impl FakeReranker {
+    pub async fn start(max_batch: usize) -> Self {
+        let batch_sizes = Arc::new(Mutex::new(Vec::new()));
+
+        let app = Router::new()
+            .route("/rerank", post(rerank))
+            .with_state((max_batch, batch_sizes.clone()));
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind fake reranker");
+        let url = format!("http://{}", listener.local_addr().expect("local address"));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        Self { url, batch_sizes }
+    }
+
+    /// Number of texts in each accepted request, in order.
+    pub fn batch_sizes(&self) -> Vec<usize> {
+        self.batch_sizes.lock().expect("batch sizes lock").clone()
+    }
+}
+
+async fn rerank(
+    State((max_batch, batch_sizes)): State<(usize, Arc<Mutex<Vec<usize>>>)>,
+    Json(request): Json<Value>,
+) -> (StatusCode, Json<Value>) {
+    let texts = request["texts"].as_array().cloned().unwrap_or_default();
+    if texts.len() > max_batch {
+        return (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            Json(json!({ "error": "batch size too large", "error_type": "Validation" })),
+        );
+    }
+    batch_sizes
+        .lock()
+        .expect("batch sizes lock")
+        .push(texts.len());
+
+    let query = request["query"].as_str().unwrap_or_default().to_lowercase();
+    let mut scores: Vec<Value> = texts
+        .iter()
+        .enumerate()
+        .map(|(index, text)| {
+            let text = text.as_str().unwrap_or_default().to_lowercase();
+            let words: Vec<&str> = text
+                .split(|c: char| !c.is_alphanumeric())
+                .filter(|word| !word.is_empty())
+                .collect();
+            let matched = words
+                .iter()
+                .filter(|word| {
+                    query
+                        .split_whitespace()
+                        .any(|query_word| query_word == **word)
+                })
+                .count();
+            json!({ "index": index, "score": matched as f32 / words.len().max(1) as f32 })
+        })
+        .collect();
+    scores.sort_by(|a, b| {
+        b["score"]
+            .as_f64()
+            .unwrap_or_default()
+            .total_cmp(&a["score"].as_f64().unwrap_or_default())
+    });
+
+    (StatusCode::OK, Json(json!(scores)))
+}
@@ -2,9 +2,48 @@ import type { Source, DocumentMetadata, DocumentListResponse } from "../types";
 
 const API_BASE_URL = import.meta.env.VITE_API_URL || "http://localhost:8080";
 
+export type RetrievalMode = "standard" | "multi_query" | "hyde";
+
+export type Language = "de" | "en";
+
 export interface ChatRequest {
   message: string;
   session_id?: string;
+  retrieval_mode?: RetrievalMode;
+  top_k?: number;
+  semantic_ratio?: number;
+  filter?: string;
+  filters?: StructuredFilters;
+  document_ids?: string[];
+  neighbor_chunks?: number;
+  prompt_template?: string;
+  language?: Language;
+  model?: string;
+  temperature?: number;
+  top_p?: number;
+  max_tokens?: number;
+  stop?: string[];
+  seed?: number;
+  agent?: boolean;
+  follow_ups?: boolean;
+}
+
+export interface StructuredFilters {
+  tags?: string[];
+  author?: string;
+  document_type?: string;
+  language?: Language;
+  created_after?: string;
+  created_before?: string;
+}
+
+export type FeedbackRating = "up" | "down";
+
+export interface FeedbackRequest {
+  request_id: string;
+  rating: FeedbackRating;
+  comment?: string;
+  wrong_source_id?: string;
 }
 
 export interface ChatResponse {
@@ -13,9 +52,47 @@ export interface ChatResponse {
   session_id?: string;
 }
 
+export interface SearchResult {
+  id: string;
+  title: string;
+  content: string;
+  metadata: DocumentMetadata;
+  source_file?: string | null;
+  /** Chunks of the same document merged into this source */
+  merged_chunk_ids?: string[];
+}
+
+export interface ToolCallEvent {
+  id: string;
+  name: string;
+  arguments: unknown;
+}
+
+export interface ToolResultEvent {
+  id: string;
+  name: string;
+  result: string;
+  sources: SearchResult[];
+}
+
+export interface ChatStreamHandlers {
+  /** Id for `cancelChat` and feedback, sent first */
+  onRequest?: (requestId: string) => void;
+  onQuery?: (query: string) => void;
+  onSources?: (sources: SearchResult[]) => void;
+  onToolCall?: (call: ToolCallEvent) => void;
+  onToolResult?: (result: ToolResultEvent) => void;
+  onFollowUps?: (questions: string[]) => void;
+  onToken: (token: string) => void;
+  onComplete: (done: Record<string, unknown>) => void;
+  onError: (error: Error) => void;
+}
+
 export interface UploadResponse {
   task_id: string;
   status: string;
+  flagged?: boolean;
+  injection_rules?: string[];
 }
 
 export interface TaskStatus {
@@ -114,11 +191,49 @@ export class ApiService {
     return handleResponse<DocumentListResponse>(response);
   }
 
+  async explainSearch(
+    request: Pick<
+      ChatRequest,
+      "top_k" | "semantic_ratio" | "filter" | "filters" | "document_ids" | "language"
+    > & { query: string },
+  ): Promise<Record<string, unknown>> {
+    const response = await fetch(`${this.baseUrl}/api/search/explain`, {
+      method: "POST",
+      headers: { "Content-Type": "application/json" },
+      body: JSON.stringify(request),
+    });
+    return handleResponse<Record<string, unknown>>(response);
+  }
+
+  async submitFeedback(feedback: FeedbackRequest): Promise<void> {
+    const response = await fetch(`${this.baseUrl}/api/feedback`, {
+      method: "POST",
+      headers: { "Content-Type": "application/json" },
+      body: JSON.stringify(feedback),
+    });
+    await handleResponse<unknown>(response);
+  }
+
+  async deleteDocument(documentId: string): Promise<void> {
+    const response = await fetch(
+      `${this.baseUrl}/api/documents/${encodeURIComponent(documentId)}`,
+      { method: "DELETE" },
+    );
+    if (!response.ok) {
+      throw new ApiError("Failed to delete document", response.status);
+    }
+  }
+
+  async cancelChat(requestId: string): Promise<void> {
+    await fetch(
+      `${this.baseUrl}/api/chat/${encodeURIComponent(requestId)}/cancel`,
+      { method: "POST" },
+    );
+  }
+
   async chatStream(
     message: string,
-    onResult: (result: string) => void,
-    onComplete: () => void,
-    onError: (error: Error) => void,
+    handlers: ChatStreamHandlers,
     sessionId?: string,
   ): Promise<void> {
     try {
@@ -144,12 +259,62 @@ export class ApiService {
 
       const decoder = new TextDecoder();
       let buffer = "";
+      let eventName = "message";
+      let dataLines: string[] = [];
+
+      const dispatch = (): boolean => {
+        const data = dataLines.join("\n");
+        const name = eventName;
+        eventName = "message";
+        dataLines = [];
+
+        if (!data) return false;
+
+        let payload: any;
+        try {
+          payload = JSON.parse(data);
+        } catch {
+          return false;
+        }
+
+        switch (name) {
+          case "request":
+            handlers.onRequest?.(payload.request_id ?? "");
+            break;
+          case "query":
+            handlers.onQuery?.(payload.query ?? "");
+            break;
+          case "sources":
+            handlers.onSources?.(payload.sources ?? []);
+            break;
+          case "tool_call":
+            handlers.onToolCall?.(payload);
+            break;
+          case "tool_result":
+            handlers.onToolResult?.(payload);
+            break;
+          case "follow_ups":
+            handlers.onFollowUps?.(payload.questions ?? []);
+            break;
+          case "token":
+            handlers.onToken(payload.content ?? "");
+            break;
+          case "error":
+            handlers.onError(new Error(payload.message ?? data));
+            break;
+          case "done":
+            handlers.onComplete(payload);
+            return true;
+        }
+        return false;
+      };
 
       while (true) {
         const { done, value } = await reader.read();
 
         if (done) {
-          onComplete();
+          dispatch();
+          handlers.onComplete({});
           break;
         }
 
@@ -157,36 +322,23 @@ export class ApiService {
         const lines = buffer.split("\n");
         buffer = lines.pop() || "";
 
-        for (const line of lines) {
-          if (line.startsWith("data: ")) {
-            const data = line.slice(6);
+        for (const rawLine of lines) {
+          const line = rawLine.replace(/\r$/, "");
 
-            if (data === "[DONE]") {
-              onComplete();
+          if (line === "") {
+            if (dispatch()) {
+              await reader.cancel();
               return;
             }
-
-            console.log("data", data);
-
-            try {
-              // Try to parse as JSON (for sources)
-              const parsed = JSON.parse(data);
-              if ("content" in parsed) {
-                onResult(parsed.content);
-              } else {
-                onResult(data);
-              }
-            } catch {
-              // If not JSON, treat as text chunk
-              if (data.trim()) {
-                onResult(data);
-              }
-            }
+          } else if (line.startsWith("event:")) {
+            eventName = line.slice(6).trim();
+          } else if (line.startsWith("data:")) {
+            dataLines.push(line.slice(5).replace(/^ /, ""));
           }
         }
       }
     } catch (error) {
-      onError(error instanceof Error ? error : new Error(String(error)));
+      handlers.onError(error instanceof Error ? error : new Error(String(error)));
     }
   }
 
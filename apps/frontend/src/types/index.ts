@@ -3,6 +3,7 @@ export interface Message {
   type: "user" | "assistant";
   content: string;
   sources?: Source[];
+  searchQuery?: string;
   timestamp: Date;
 }
 